@@ -0,0 +1,45 @@
+//! Compare scrolling and rotation block merges across element sizes.
+//!
+//! Run with `cargo run --release --example block_merge`.
+
+use std::time::{Duration, Instant};
+
+use aerosort::SortConfig;
+
+// Sort random elements of `N` words with each configuration, and return the time each took.
+fn compare<const N: usize>(configs: [SortConfig; 2]) -> [Duration; 2] {
+    let n = (1 << 24) / (8 * N);
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    let v: Vec<[u64; N]> = (0..n)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            [state; N]
+        })
+        .collect();
+
+    configs.map(|config| {
+        let mut w = v.clone();
+        let start = Instant::now();
+        config.sort_by(&mut w, |x, y| x[0].cmp(&y[0]));
+        let elapsed = start.elapsed();
+        assert!(w.windows(2).all(|w| w[0][0] <= w[1][0]));
+        elapsed
+    })
+}
+
+fn main() {
+    let configs = [SortConfig::new(), SortConfig::new().max_scrolling_size(0)];
+    let results = [
+        (8, compare::<1>(configs)),
+        (64, compare::<8>(configs)),
+        (512, compare::<64>(configs)),
+        (4096, compare::<512>(configs)),
+    ];
+
+    println!("{:>6} {:>12} {:>12}", "bytes", "scrolling", "rotation");
+    for (bytes, [scrolling, rotation]) in results {
+        println!("{bytes:>6} {scrolling:>12.2?} {rotation:>12.2?}");
+    }
+}
@@ -0,0 +1,35 @@
+//! Compare sorting random `u32`s with the sorting network base case of `sort_primitive` and with
+//! the insertion sort base case of `sort`, on short slices and on long ones.
+//!
+//! Run with `cargo run --release --features network --example network`.
+
+use std::time::{Duration, Instant};
+
+// Sort `count` slices of `n` random values each with `sort`, and return the time it took.
+fn time(n: usize, count: usize, sort: fn(&mut [u32])) -> Duration {
+    let mut state = 0x9e37_79b9u32;
+    let v: Vec<u32> = (0..n * count)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        })
+        .collect();
+
+    let mut w = v.clone();
+    let start = Instant::now();
+    w.chunks_mut(n).for_each(sort);
+    let elapsed = start.elapsed();
+    assert!(w.chunks(n).all(|c| c.windows(2).all(|p| p[0] <= p[1])));
+    elapsed
+}
+
+fn main() {
+    println!("{:>8} {:>8} {:>12} {:>12}", "n", "count", "network", "insertion");
+    for (n, count) in [(8, 1 << 17), (16, 1 << 16), (32, 1 << 15), (1 << 20, 1)] {
+        let network = time(n, count, aerosort::sort_primitive);
+        let insertion = time(n, count, aerosort::sort);
+        println!("{n:>8} {count:>8} {network:>12.2?} {insertion:>12.2?}");
+    }
+}
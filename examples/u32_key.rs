@@ -0,0 +1,37 @@
+//! Compare sorting by a 32-bit key with radix sorts and with a comparison sort.
+//!
+//! Run with `cargo run --release --features radix --example u32_key`.
+
+use std::time::Instant;
+
+#[derive(Clone, Copy)]
+struct Record {
+    hash: u32,
+    _payload: [u32; 3],
+}
+
+type Sort = fn(&mut [Record], &mut [Record]);
+
+fn main() {
+    let v: Vec<Record> = (0..1_000_000u32)
+        .map(|i| Record { hash: i.wrapping_mul(0x9e37_79b9).rotate_left(7), _payload: [i; 3] })
+        .collect();
+    let mut buf = v.clone();
+
+    let sorts: [(&str, Sort); 3] = [
+        ("sort_by_key", |v, _| aerosort::sort_by_key(v, |x| x.hash)),
+        ("sort_unstable_by_u32_key", |v, _| aerosort::sort_unstable_by_u32_key(v, |x| x.hash)),
+        ("sort_unstable_with_by_u32_key", |v, buf| {
+            aerosort::sort_unstable_with_by_u32_key(v, buf, |x| x.hash)
+        }),
+    ];
+
+    for (name, sort) in sorts {
+        let mut w = v.clone();
+        let start = Instant::now();
+        sort(&mut w, &mut buf);
+        let elapsed = start.elapsed();
+        assert!(w.windows(2).all(|w| w[0].hash <= w[1].hash));
+        println!("{name:>30} {elapsed:>12.2?}");
+    }
+}
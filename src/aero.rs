@@ -1,6 +1,8 @@
 use crate::keys::Keys;
 use crate::merge::Merge;
-use crate::mini::insertion_sort_safe;
+use crate::mini::{BaseSort, InsertionSort};
+use crate::trace::trace;
+use crate::SortConfig;
 
 /// Perform a merge operation, prioritizing external buffer merges.
 ///
@@ -13,24 +15,47 @@ pub fn merge_regular<T, F: FnMut(&T, &T) -> bool>(
         .or(|| keys.merge([a, b], less));
 }
 
-// Sort `v` using a merge strategy `merge`.
+// Sort `v` using a merge strategy `merge`, sorting each piece with `base`, checking `cancel` once
+// per level of merges and giving up if it returns `true`. Return `false` iff we gave up.
 fn sort_with_merge_strategy<T, F: FnMut(&T, &T) -> bool>(
-    v: &mut [T], less: &mut F, mut merge: impl FnMut([&mut [T]; 2], &mut F),
-) {
+    v: &mut [T], config: &SortConfig, base: &mut impl BaseSort<T>, less: &mut F,
+    cancel: &mut impl FnMut() -> bool, mut merge: impl FnMut([&mut [T]; 2], &mut F),
+) -> bool {
+    if config.natural {
+        return crate::runs::sort_natural(v, less, cancel, merge);
+    }
+
+    // A task left over after collecting keys may have fewer than 2 elements
     let n = v.len();
+    if n < 2 {
+        return true;
+    }
+
+    // We split `v` into `factor` pieces of `n / factor` elements, rounded either way. With
+    // `n <= 16`, `factor == 1`; otherwise, `n / 16 <= factor < n / 8`, so every piece has from 8 to
+    // 16 elements. `factor` is never 0, so `bound` never divides by zero.
+    let factor = (1 << sort_util::op::log2_ceil(n).saturating_sub(4)) as u128;
 
-    // `0 <= i <= factor <= n <= isize::MAX` (`isize::MAX` is the maximum slice length), so we can
-    // fit `n * i <= isize::MAX * isize::MAX < 2^126` in a u128.
-    #[allow(clippy::precedence)]    // Who cares
-    let factor = (1 << sort_util::op::log2_ceil(n) - 4) as u128;
+    // `1 <= i <= factor < n <= isize::MAX` (`isize::MAX` is the maximum slice length), so we can
+    // fit `n * i < isize::MAX * isize::MAX < 2^126` in a u128, as long as `usize` has at most 64
+    // bits
+    const { assert!(usize::BITS <= 64) };
     let bound = |i| (n as u128 * i / factor) as usize;
 
-    // Merge sort loop
+    // Merge sort loop. Two runs are merged as soon as both are sorted, which is the order of a
+    // recursive merge sort: every merge reads data its last sub-merge just wrote, so once a run
+    // fits in a cache level, it is sorted without leaving it, whatever the cache sizes are.
     let mut right = 0;
     let mut mid;
     for i in 1..=factor {
+        // Check `cancel` whenever the sorted run at the front is about to double in length, so once
+        // per level of merges rather than once per piece
+        if i.is_power_of_two() && cancel() {
+            return false;
+        }
+
         [mid, right] = [right, bound(i)];
-        insertion_sort_safe(&mut v[mid..right], less);
+        base.sort(&mut v[mid..right], less);
 
         for k in 1..=i.trailing_zeros() {
             let left = bound(i - (1 << k));
@@ -39,56 +64,221 @@ fn sort_with_merge_strategy<T, F: FnMut(&T, &T) -> bool>(
             mid = left;
         }
     }
+
+    true
 }
 
 // Sort `v` using `ext` as an external buffer and `keys`.
-fn sort<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], ext: &mut [T], keys: &mut Keys<T>, less: &mut F) {
-    sort_with_merge_strategy(v, less, |[a, b], less| merge_regular([a, b], ext, keys, less));
+fn sort<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], ext: &mut [T], keys: &mut Keys<T>, config: &SortConfig,
+    base: &mut impl BaseSort<T>, less: &mut F, cancel: &mut impl FnMut() -> bool,
+) -> bool {
+    if core::mem::size_of::<T>() > config.max_scrolling_size {
+        keys.disable_scrolling();
+    }
+
+    sort_with_merge_strategy(v, config, base, less, cancel, |[a, b], less| {
+        merge_regular([a, b], ext, keys, less)
+    })
 }
 
 // Sort `v` with in-place merging.
-fn sort_lazy<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], less: &mut F) {
-    sort_with_merge_strategy(v, less, |[a, b], less| crate::merge::merge_in_place([a, b], less) );
+fn sort_lazy<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], config: &SortConfig, base: &mut impl BaseSort<T>, less: &mut F,
+    cancel: &mut impl FnMut() -> bool,
+) -> bool {
+    sort_with_merge_strategy(v, config, base, less, cancel, |[a, b], less| {
+        crate::merge::merge_in_place([a, b], less)
+    })
 }
 
 // Sort `v` with `ext` as an external buffer, assuming we can use it for every merge.
-fn sort_easy<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], ext: &mut [T], less: &mut F) {
-    sort_with_merge_strategy(v, less, |[a, b], less| { ext.merge([a, b], less); });
+fn sort_easy<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], ext: &mut [T], config: &SortConfig, base: &mut impl BaseSort<T>, less: &mut F,
+    cancel: &mut impl FnMut() -> bool,
+) -> bool {
+    sort_with_merge_strategy(v, config, base, less, cancel, |[a, b], less| {
+        ext.merge([a, b], less);
+    })
+}
+
+/// Sort `v` with `merge` performing every merge, sorting short pieces with `base`.
+///
+/// Cost: `O(n log n)` comparisons and moves on top of `base` and `merge`.
+#[cfg(feature = "x86-simd")]
+pub fn sort_merging<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], base: &mut impl BaseSort<T>, less: &mut F,
+    merge: impl FnMut([&mut [T]; 2], &mut F),
+) {
+    sort_with_merge_strategy(v, &SortConfig::DEFAULT, base, less, &mut || false, merge);
+}
+
+/// The way [`sort_with_strategy`] merges runs.
+#[cfg(feature = "internal")]
+pub enum SortStrategy<'a, 'b, T> {
+    /// Merge with the external buffer whenever it can hold the shorter run, and with a collection
+    /// of keys otherwise (see [`merge_regular`]). This is what a sort does once it collects more
+    /// than 12 keys.
+    Keys(&'a mut Keys<'b, T>),
+
+    /// Merge with rotations only, ignoring the external buffer. This is what a sort does when it
+    /// collects too few keys to merge with them.
+    Lazy,
+
+    /// Merge with the external buffer only, which must hold at least half of the slice. This is
+    /// what a sort does when given such a buffer.
+    Easy,
+}
+
+/// Sort `v` with `ext` as an external buffer, merging runs with `strategy` regardless of whether a
+/// sort would choose it.
+///
+/// Cost: `O(n log n)` comparisons and `O(n log n)` moves for [`SortStrategy::Easy`], and for
+/// [`SortStrategy::Keys`] with keys collected from a slice containing `v`; `O(n log^2 n)` moves for
+/// [`SortStrategy::Lazy`].
+///
+/// # Safety
+///
+/// With [`SortStrategy::Keys`], the keys must not overlap `v`, and must be a collection of keys as
+/// [`crate::state`] produces it: a nonempty set of distinct elements whose tags are sorted and
+/// whose buffer elements are greater than every tag. The other strategies have no requirements.
+///
+/// # Panics
+///
+/// Panics if the strategy is [`SortStrategy::Easy`] and `ext` is shorter than `v.len() / 2`.
+#[cfg(feature = "internal")]
+pub unsafe fn sort_with_strategy<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], ext: &mut [T], strategy: SortStrategy<T>, less: &mut F,
+) {
+    let (config, base) = (&SortConfig::DEFAULT, &mut InsertionSort);
+    match strategy {
+        SortStrategy::Keys(keys) => sort(v, ext, keys, config, base, less, &mut || false),
+        SortStrategy::Lazy => sort_lazy(v, config, base, less, &mut || false),
+        SortStrategy::Easy => {
+            assert!(ext.len() >= v.len() / 2, "external buffer is shorter than half of the slice");
+            sort_easy(v, ext, config, base, less, &mut || false)
+        }
+    };
+}
+
+/// Return the length up to which we insertion sort instead of merging. Insertion sort performs
+/// `O(n^2)` moves, so we lower the threshold as elements get larger: small elements are cheap to
+/// shift, while large ones quickly make merging the better choice. The threshold is never below 16,
+/// as our merge sort loop assumes at least 16 elements.
+const fn small_sort_threshold<T>() -> usize {
+    match core::mem::size_of::<T>() {
+        0..=16 => 64,
+        17..=64 => 32,
+        _ => 16,
+    }
 }
 
 /// Sort `v` with `ext` as an external buffer.
 ///
+/// Every loop in our merges is bounded by run, block, and buffer lengths rather than by comparison
+/// results, so an inconsistent comparison function can leave `v` unsorted but never makes us touch
+/// memory outside of `v` and `ext`. With the `check-order` feature, we verify the result and fall
+/// back to heap sort, which never relies on earlier comparisons, if it is not sorted.
+///
 /// Cost: `O(n log n)` comparisons and `O(n log n)` moves.
-pub fn sort_full<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], ext: &mut [T], less: &mut F) {
+pub fn sort_full<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], ext: &mut [T], config: &SortConfig, less: &mut F,
+) {
+    sort_cancellable(v, ext, config, &mut InsertionSort, less, &mut || false);
+}
+
+/// Sort `v` with `ext` as an external buffer as [`sort_full`] does, sorting short pieces with
+/// `base`, calling `cancel` once per level of merges and stopping as soon as it returns `true`.
+/// Return `false` iff the sort was cancelled, in which case `v` holds all of its original elements
+/// in an unspecified order.
+///
+/// Cost: `O(n log n)` comparisons and `O(n log n)` moves on top of `base`, and `O(log n)` calls to
+/// `cancel`, or one per run when merging natural runs.
+pub fn sort_cancellable<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], ext: &mut [T], config: &SortConfig, base: &mut impl BaseSort<T>, less: &mut F,
+    cancel: &mut impl FnMut() -> bool,
+) -> bool {
+    if !sort_dispatch(v, ext, config, base, less, cancel) {
+        return false;
+    }
+
+    #[cfg(feature = "check-order")]
+    if crate::runs::sorted_prefix(v, less) != v.len() {
+        crate::mini::heap_sort(v, less);
+    }
+
+    true
+}
+
+// Sort `v` with `ext` as an external buffer, choosing a strategy based on its length and contents.
+// Return `false` iff `cancel` stopped the sort.
+fn sort_dispatch<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], ext: &mut [T], config: &SortConfig, base: &mut impl BaseSort<T>, less: &mut F,
+    cancel: &mut impl FnMut() -> bool,
+) -> bool {
     let n = v.len();
 
-    // Use insertion sort for small arrays
-    if n <= 64 {
-        return insertion_sort_safe(v, less);
+    // Use the base sort for small arrays
+    if n <= small_sort_threshold::<T>() {
+        trace!("sort_full: small n={n}");
+        base.sort(v, less);
+        return true;
     }
 
     // If our buffer is sufficiently large, we can be sure that it can perform every merge
     if ext.len() >= n / 2 {
-        return sort_easy(v, ext, less);
+        // Skip constant slices, which a stable sort leaves as they are; without a buffer this
+        // large, collecting keys finds a single one. Only slices whose ends are equal are scanned,
+        // so this costs 2 comparisons on most other input.
+        let ends_equal = !less(&v[0], &v[n - 1]) && !less(&v[n - 1], &v[0]);
+        if ends_equal && crate::runs::sorted_prefix(v, less) == n {
+            trace!("sort_full: constant n={n}");
+            return true;
+        }
+
+        trace!("sort_full: easy n={n}");
+        return sort_easy(v, ext, config, base, less, cancel);
     }
 
     // Collect keys and sort
-    let mut state = crate::state::collect_keys(v, less);
+    let mut state = crate::state::collect_keys_with_factor(v, config.key_factor, less);
+    let k = state.key_count();
 
-    match state.keys.inner.len() {
+    match k {
         // We have done something wrong
         0 => unsafe { core::hint::unreachable_unchecked() },
 
         // If the slice turns out to contain 1 value, we are done
-        1 => (),
+        1 => {
+            trace!("sort_full: single n={n}");
+            true
+        }
 
-        // If the slice turns out to contain 12 or less values, just use rotation-based merging
-        2..=12 => sort_lazy(v, less),
+        // If the slice turns out to contain 12 or less values, distribute directly among them.
+        // Every partition takes `O(n)` comparisons, and `O(n)` moves as long as `ext` holds a
+        // fixed fraction of the slice, or `O(n log n)` moves by rotations otherwise.
+        2..=12 if state.complete => {
+            trace!("sort_full: distribute n={n} keys={k}");
+            state.distribute_by(ext, less);
+            true
+        }
+
+        // If we could not collect every value, we must have asked for at most 12 keys, so the slice
+        // is short enough (`n * factor < 169`) that rotation-based merging cannot degrade past
+        // `O(n log n)`
+        2..=12 => {
+            debug_assert!(n * config.key_factor < 169);
+            trace!("sort_full: lazy n={n} keys={k}");
+            sort_lazy(v, config, base, less, cancel)
+        }
 
-        // Perform normal block merge sort
+        // Perform normal block merge sort; if cancelled, we still redistribute our keys, so that
+        // the slice is left as a permutation of its original elements
         13.. => {
-            sort(state.task, ext, &mut state.keys, less);
+            trace!("sort_full: keys n={n} keys={k}");
+            let done = sort(state.task, ext, &mut state.keys, config, base, less, cancel);
             state.restore_by(less);
+            done
         }
     }
 }
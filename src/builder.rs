@@ -0,0 +1,133 @@
+use core::cmp::Ordering;
+
+use crate::{Buffer, SortConfig};
+
+/// The order a [`SortBuilder`] sorts by: [`Natural`] order, or a comparison function.
+pub trait SortOrder<T> {
+    /// Compare `x` and `y`.
+    fn compare(&mut self, x: &T, y: &T) -> Ordering;
+}
+
+/// The natural order of an [`Ord`] type, which a [`SortBuilder`] sorts by unless given another.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Natural;
+
+impl<T: Ord> SortOrder<T> for Natural {
+    fn compare(&mut self, x: &T, y: &T) -> Ordering {
+        x.cmp(y)
+    }
+}
+
+impl<T, F: FnMut(&T, &T) -> Ordering> SortOrder<T> for F {
+    fn compare(&mut self, x: &T, y: &T) -> Ordering {
+        self(x, y)
+    }
+}
+
+/// A sort of a slice with options chosen one at a time, for combinations that no free function
+/// covers. [`SortBuilder::run`] forwards to [`SortConfig::sort_with_by`], or to the sort of
+/// [`crate::sort_unstable_by`] if [`SortBuilder::unstable`] was chosen; every other combination is
+/// a stable sort.
+///
+/// ```
+/// use aerosort::{SortBuilder, SortConfig};
+///
+/// type Builder<'a> = SortBuilder<'a, (u32, usize), &'a mut [(u32, usize)]>;
+///
+/// // Start a sort with a buffer and a configuration, unstable if asked to be
+/// fn start<'a>(v: &'a mut [(u32, usize)], buf: &'a mut [(u32, usize)], config: SortConfig,
+///     unstable: bool) -> Builder<'a>
+/// {
+///     let builder = SortBuilder::new(v).with_buffer(buf).config(config);
+///     if unstable { builder.unstable() } else { builder }
+/// }
+///
+/// let natural = SortConfig::new().natural_runs(true);
+/// for n in [6, 1000] {
+///     let input: Vec<(u32, usize)> = (0..n).map(|i| (i as u32 * 7919 % 13, i)).collect();
+///     for ext in [0, 100, 500] {
+///         for config in [SortConfig::new(), natural] {
+///             for unstable in [false, true] {
+///                 let mut buf = vec![(0, 0); ext];
+///
+///                 let mut v = input.clone();
+///                 start(&mut v, &mut buf, config, unstable).run();
+///                 assert!(v.windows(2).all(|w| w[0] < w[1]));
+///
+///                 // Elements with equal keys keep their order unless the sort is unstable
+///                 let mut v = input.clone();
+///                 start(&mut v, &mut buf, config, unstable).by_key(|x| x.0).run();
+///                 assert!(v.windows(2).all(|w| w[0].0 <= w[1].0));
+///                 assert!(unstable || v.windows(2).all(|w| w[0].0 < w[1].0 || w[0] < w[1]));
+///
+///                 let mut v = input.clone();
+///                 let cmp = |x: &(u32, usize), y: &(u32, usize)| y.0.cmp(&x.0);
+///                 start(&mut v, &mut buf, config, unstable).by(cmp).run();
+///                 assert!(v.windows(2).all(|w| w[0].0 >= w[1].0));
+///                 assert!(unstable || v.windows(2).all(|w| w[0].0 > w[1].0 || w[0] < w[1]));
+///             }
+///         }
+///     }
+/// }
+/// ```
+pub struct SortBuilder<'a, T, B = [T; 0], C = Natural> {
+    v: &'a mut [T],
+    ext: B,
+    config: SortConfig,
+    order: C,
+    stable: bool,
+}
+
+impl<'a, T> SortBuilder<'a, T> {
+    /// Start a sort of `v` by natural order, in place, with the default configuration.
+    pub fn new(v: &'a mut [T]) -> Self {
+        Self { v, ext: [], config: SortConfig::DEFAULT, order: Natural, stable: true }
+    }
+}
+
+impl<'a, T, B: Buffer<T>, C: SortOrder<T>> SortBuilder<'a, T, B, C> {
+    /// Use `ext` as an external buffer (see [`crate::sort_with`]).
+    pub fn with_buffer<E: Buffer<T>>(self, ext: E) -> SortBuilder<'a, T, E, C> {
+        let Self { v, config, order, stable, .. } = self;
+        SortBuilder { v, ext, config, order, stable }
+    }
+
+    /// Sort with the configuration `config`.
+    pub fn config(self, config: SortConfig) -> Self {
+        Self { config, ..self }
+    }
+
+    /// Let equal elements end up in any order, as [`crate::sort_unstable`] does.
+    pub fn unstable(self) -> Self {
+        Self { stable: false, ..self }
+    }
+
+    /// Sort with a comparison function `cmp`.
+    pub fn by<F>(self, cmp: F) -> SortBuilder<'a, T, B, F>
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        let Self { v, ext, config, stable, .. } = self;
+        SortBuilder { v, ext, config, order: cmp, stable }
+    }
+
+    /// Sort with a mapping `f` from elements to keys.
+    pub fn by_key<K: Ord>(
+        self, f: impl FnMut(&T) -> K,
+    ) -> SortBuilder<'a, T, B, impl FnMut(&T, &T) -> Ordering> {
+        self.by(crate::cmp::by_key(f))
+    }
+
+    /// Sort the slice.
+    #[inline(always)]
+    pub fn run(self) {
+        let Self { v, mut ext, config, mut order, stable } = self;
+        if stable {
+            config.sort_with_by(v, ext, |x, y| order.compare(x, y))
+        } else {
+            let ext = crate::scratch::slots(&mut ext);
+            let less = &mut |x: &T, y: &T| order.compare(x, y) == Ordering::Less;
+            crate::sort_unstable_configured(v, ext, &config, less)
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Combinators for building comparison functions to pass to the `_by` interface.
+//!
+//! ```
+//! use aerosort::cmp::{by_key, Compare};
+//!
+//! let mut v = [(1, 'b'), (0, 'a'), (1, 'a'), (0, 'b')];
+//! aerosort::sort_by(&mut v, by_key(|x: &(i32, char)| x.0).then_by(|x| x.1).reverse());
+//! assert_eq!(v, [(1, 'b'), (1, 'a'), (0, 'b'), (0, 'a')]);
+//! ```
+
+use core::cmp::Ordering;
+
+/// Return a comparison function that compares elements by a mapping `f` from elements to keys.
+pub fn by_key<T, K: Ord>(mut f: impl FnMut(&T) -> K) -> impl FnMut(&T, &T) -> Ordering {
+    move |x, y| f(x).cmp(&f(y))
+}
+
+/// Compare references by the addresses they point to. Breaking ties with this, e.g. with
+/// [`Compare::then_with`], sorts a slice of references into the same order whatever order it
+/// started in, which stability alone does not: a stable sort keeps equal references in their
+/// input order.
+///
+/// ```
+/// use aerosort::cmp::{by_address, by_key, Compare};
+///
+/// let values = [1, 0, 1, 1];
+/// let sorted = |mut v: Vec<&i32>| {
+///     aerosort::sort_by(&mut v, by_key(|x: &&i32| **x).then_with(by_address));
+///     v.into_iter().map(|x| x as *const i32).collect::<Vec<_>>()
+/// };
+///
+/// let forward = sorted(values.iter().collect());
+/// assert_eq!(forward, sorted(values.iter().rev().collect()));
+/// assert_eq!(forward[0], &values[1] as *const i32);
+/// ```
+pub fn by_address<T: ?Sized>(x: &&T, y: &&T) -> Ordering {
+    (*x as *const T).cast::<()>().cmp(&(*y as *const T).cast())
+}
+
+/// An extension trait for chaining comparison functions. This is implemented for every
+/// comparison function.
+pub trait Compare<T>: FnMut(&T, &T) -> Ordering + Sized {
+    /// Break ties of this comparison function with a mapping `f` from elements to keys.
+    fn then_by<K: Ord>(self, mut f: impl FnMut(&T) -> K) -> impl FnMut(&T, &T) -> Ordering {
+        self.then_with(move |x, y| f(x).cmp(&f(y)))
+    }
+
+    /// Break ties of this comparison function with another comparison function `cmp`.
+    fn then_with(
+        mut self, mut cmp: impl FnMut(&T, &T) -> Ordering,
+    ) -> impl FnMut(&T, &T) -> Ordering {
+        move |x, y| self(x, y).then_with(|| cmp(x, y))
+    }
+
+    /// Reverse this comparison function. Sorting stays stable: equal elements keep their order.
+    fn reverse(mut self) -> impl FnMut(&T, &T) -> Ordering {
+        move |x, y| self(y, x)
+    }
+}
+
+impl<T, F: FnMut(&T, &T) -> Ordering> Compare<T> for F {}
+
+/// A floating-point type with a total order, as defined by IEEE 754's `totalOrder` predicate.
+pub trait Float: Copy {
+    /// Compare `self` and `other` by total order.
+    fn total_cmp(&self, other: &Self) -> Ordering;
+}
+
+impl Float for f32 {
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        f32::total_cmp(self, other)
+    }
+}
+
+impl Float for f64 {
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        f64::total_cmp(self, other)
+    }
+}
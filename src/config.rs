@@ -0,0 +1,130 @@
+use core::cmp::Ordering;
+
+use sort_util::buffer;
+
+use crate::Buffer;
+
+/// Options for tuning how a sort spends its comparisons and moves.
+///
+/// ```
+/// // Collect `2 sqrt n` keys instead of `sqrt 2n`, trading moves for fewer comparisons
+/// let config = aerosort::SortConfig::new().key_factor(4);
+///
+/// let mut v: Vec<u32> = (0..1000).rev().collect();
+/// config.sort(&mut v);
+/// assert!(v.windows(2).all(|w| w[0] <= w[1]));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SortConfig {
+    pub(crate) key_factor: usize,
+    pub(crate) natural: bool,
+    pub(crate) max_scrolling_size: usize,
+}
+
+impl SortConfig {
+    /// The default configuration.
+    pub const DEFAULT: Self =
+        Self { key_factor: 2, natural: false, max_scrolling_size: usize::MAX };
+
+    /// Return the default configuration.
+    pub const fn new() -> Self {
+        Self::DEFAULT
+    }
+
+    /// Collect around `sqrt(factor * n)` keys when sorting without a sufficient external buffer.
+    /// The default factor is 2. A factor of 4 (`2 sqrt n` keys) reduces comparisons by around 1%
+    /// with large `n`, but makes the final redistribution of keys more expensive. A factor of 0 is
+    /// treated as 1.
+    pub const fn key_factor(mut self, factor: usize) -> Self {
+        self.key_factor = if factor == 0 { 1 } else { factor };
+        self
+    }
+
+    /// Merge the natural runs of a slice instead of sorting it in fixed-size pieces when `enabled`.
+    /// Maximal non-descending and strictly descending runs are found first, and runs shorter than
+    /// 16 elements are extended with insertion sort. This greatly reduces comparisons and moves on
+    /// partially sorted input, such as a sorted slice with a few elements appended. On random input
+    /// it still takes `O(n log n)` comparisons, with a larger constant factor. Disabled by default.
+    ///
+    /// Runs are merged in the nearly balanced order of powersort, and a run that continues the one
+    /// before it once descending runs are reversed is joined to it rather than merged. Sawtooth
+    /// data rising and falling in steps upwards then sorts in about `n` comparisons, where merging
+    /// its `r` runs takes around `n log r`:
+    ///
+    /// ```
+    /// use std::cell::Cell;
+    ///
+    /// // Teeth that rise, then fall from above where they rose to
+    /// let tooth = |i: u32| if i < 32 { i } else { 127 - i };
+    /// let v: Vec<u32> = (0..1 << 12).map(|i| i / 64 * 128 + tooth(i % 64)).collect();
+    /// let comparisons = Cell::new(0);
+    /// let cmp = |x: &u32, y: &u32| { comparisons.set(comparisons.get() + 1); x.cmp(y) };
+    ///
+    /// let mut w = v.clone();
+    /// let config = aerosort::SortConfig::new().natural_runs(true);
+    /// config.sort_with_by(&mut w, &mut vec![0; 1 << 11][..], cmp);
+    /// let natural = comparisons.replace(0);
+    ///
+    /// // Merge the same runs, reversing the descending ones, without joining any
+    /// let mut u = v.clone();
+    /// let mut boundaries = vec![0; 1 << 11];
+    /// let k = aerosort::detect_runs_by(&v, cmp, &mut boundaries);
+    /// let starts = [0].iter().chain(&boundaries[..k]);
+    /// let descending: Vec<bool> = starts.map(|&i| v[i + 1] < v[i]).collect();
+    /// aerosort::merge_runs_directed_by(&mut u, &boundaries[..k], &descending, cmp);
+    ///
+    /// assert_eq!(w, u);
+    /// if !cfg!(feature = "check-order") {
+    ///     assert!(natural < 4400 && 2 * natural < comparisons.get());
+    /// }
+    /// ```
+    pub const fn natural_runs(mut self, enabled: bool) -> Self {
+        self.natural = enabled;
+        self
+    }
+
+    /// Block merge with the key buffer scrolling through the runs only for elements of at most
+    /// `bytes` bytes, and with rotations for larger ones. Scrolling swaps every element through the
+    /// buffer, but rotations move large elements just as often; the `block_merge` example compares
+    /// both, and scrolling stays ahead up to elements of 4 KiB as the gap narrows, so no size is
+    /// excluded by default.
+    ///
+    /// ```
+    /// let config = aerosort::SortConfig::new().max_scrolling_size(256);
+    ///
+    /// let mut v: Vec<[u64; 64]> = (0..1000).map(|i| [i * 7919 % 1009; 64]).collect();
+    /// config.sort(&mut v);
+    /// assert!(v.windows(2).all(|w| w[0] <= w[1]));
+    /// ```
+    pub const fn max_scrolling_size(mut self, bytes: usize) -> Self {
+        self.max_scrolling_size = bytes;
+        self
+    }
+
+    /// Sort `v` with this configuration.
+    #[inline(always)]
+    pub fn sort<T: Ord>(&self, v: &mut [T]) {
+        self.sort_by(v, T::cmp)
+    }
+
+    /// Sort `v` with this configuration and a comparison function `cmp`.
+    #[inline(always)]
+    pub fn sort_by<T>(&self, v: &mut [T], cmp: impl FnMut(&T, &T) -> Ordering) {
+        self.sort_with_by(v, buffer::create(0), cmp)
+    }
+
+    /// Sort `v` with this configuration, an external buffer `ext`, and a comparison function `cmp`.
+    #[inline(always)]
+    pub fn sort_with_by<T>(
+        &self, v: &mut [T], mut ext: impl Buffer<T>, mut cmp: impl FnMut(&T, &T) -> Ordering,
+    ) {
+        let ext = crate::scratch::slots(&mut ext);
+        crate::sort_configured(v, ext, self, &mut |x, y| cmp(x, y) == Ordering::Less)
+    }
+}
+
+impl Default for SortConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
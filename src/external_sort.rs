@@ -0,0 +1,105 @@
+//! Sorting fixed-size records that do not fit in memory: sort each chunk that does with
+//! [`spill_sorted`], writing it to its own file, then merge the files with [`merge_sorted`].
+//!
+//! ```
+//! use std::io::Cursor;
+//!
+//! use aerosort::external_sort::{merge_sorted, spill_sorted};
+//!
+//! // 1000 records of 8 bytes: a big-endian key, then the big-endian index of the record
+//! let record = |i: u32| [(i * 7919 % 100).to_be_bytes(), i.to_be_bytes()].concat();
+//! let input: Vec<u8> = (0..1000).flat_map(record).collect();
+//!
+//! // Sort chunks of 100 records by key into separate files
+//! let by_key = |x: &[u8; 8], y: &[u8; 8]| x[..4].cmp(&y[..4]);
+//! let mut files = Vec::new();
+//! for chunk in input.chunks(800) {
+//!     let mut records: Vec<[u8; 8]> = chunk.chunks(8).map(|r| r.try_into().unwrap()).collect();
+//!     let mut file = Vec::new();
+//!     spill_sorted(&mut records, &mut file, by_key).unwrap();
+//!     files.push(Cursor::new(file));
+//! }
+//!
+//! let mut output = Vec::new();
+//! merge_sorted(&mut files, &mut output, by_key).unwrap();
+//!
+//! // Records with equal keys stay in their original order
+//! let records: Vec<&[u8]> = output.chunks(8).collect();
+//! assert_eq!(records.len(), 1000);
+//! assert!(records.windows(2).all(|w| w[0] < w[1]));
+//! ```
+
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use std::io::{self, Read, Write};
+
+/// Sort the records of `chunk` with a comparison function `cmp` and write them to `out` in order.
+/// Equal records keep their order.
+pub fn spill_sorted<const N: usize>(
+    chunk: &mut [[u8; N]], out: &mut impl Write, cmp: impl FnMut(&[u8; N], &[u8; N]) -> Ordering,
+) -> io::Result<()> {
+    crate::sort_by(chunk, cmp);
+    chunk.iter().try_for_each(|record| out.write_all(record))
+}
+
+/// Merge the streams of sorted `N`-byte records `inputs` with a comparison function `cmp`, and
+/// write the result to `out`. Equal records are written in the order of their inputs, so merging
+/// the sorted chunks of a stream in order sorts it stably.
+///
+/// This holds one record per input in memory. Wrap inputs and `out` in buffered readers and
+/// writers, as records are read and written one at a time.
+///
+/// Cost: `O(n log k)` comparisons for `k` inputs, and `O(k)` moves of indices per record.
+///
+/// # Errors
+///
+/// Returns the first error of an input or of `out`, or an error of kind
+/// [`io::ErrorKind::UnexpectedEof`] if an input ends partway through a record.
+pub fn merge_sorted<const N: usize>(
+    inputs: &mut [impl Read], out: &mut impl Write,
+    mut cmp: impl FnMut(&[u8; N], &[u8; N]) -> Ordering,
+) -> io::Result<()> {
+    const { assert!(N != 0, "records must not be empty") };
+
+    // The current record of every input
+    let mut heads = alloc::vec![[0; N]; inputs.len()];
+
+    // The inputs that have a current record, ordered from greatest to least record, breaking ties
+    // by index, so that the next record to write is always at the end
+    let mut order = Vec::with_capacity(inputs.len());
+    let mut insert = |order: &mut Vec<usize>, heads: &[[u8; N]], i: usize| {
+        let index = order.partition_point(|&j| cmp(&heads[j], &heads[i]).then(j.cmp(&i)).is_gt());
+        order.insert(index, i);
+    };
+
+    for (i, input) in inputs.iter_mut().enumerate() {
+        if read_record(input, &mut heads[i])? {
+            insert(&mut order, &heads, i);
+        }
+    }
+
+    while let Some(i) = order.pop() {
+        out.write_all(&heads[i])?;
+        if read_record(&mut inputs[i], &mut heads[i])? {
+            insert(&mut order, &heads, i);
+        }
+    }
+
+    Ok(())
+}
+
+// Read the next record of `input` into `record`. Return `false` if `input` has ended.
+fn read_record<const N: usize>(input: &mut impl Read, record: &mut [u8; N]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < N {
+        match input.read(&mut record[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(len) => filled += len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(true)
+}
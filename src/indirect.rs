@@ -0,0 +1,147 @@
+use alloc::collections::TryReserveError;
+use alloc::vec::Vec;
+use core::ptr;
+
+use crate::{metrics, SortConfig};
+
+/// The element size in bytes from which [`crate::sort_alloc`] sorts indices rather than elements.
+/// From this size, moving each element `O(1)` times rather than `O(log n)` times makes up for the
+/// cache misses of comparing through indices at every length; at 256 bytes, those misses win out
+/// on long slices, while at 512 bytes the indices are clearly faster throughout.
+pub const MIN_SIZE: usize = 384;
+
+/// Sort `v` by sorting the indices of its elements with an allocated buffer, then moving every
+/// element straight to its place. Fall back to sorting `v` in place if `v` has more than
+/// `u32::MAX` elements or allocating the indices fails.
+///
+/// Cost: that of [`crate::aero::sort_full`] in comparisons, and `O(n)` moves of elements.
+pub fn sort_indirect<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], config: &SortConfig, less: &mut F) {
+    match sorted_order(v, config, less) {
+        Some(mut order) => permute(v, &mut order),
+        None => crate::aero::sort_full(v, &mut [], config, less),
+    }
+}
+
+/// Return the indices of the elements of `v` in stably sorted order, sorting them with a buffer of
+/// `n / 2` indices, or `None` if `v` has more than `u32::MAX` elements or allocating fails.
+///
+/// Cost: that of [`crate::aero::sort_full`] in comparisons, and `3n / 2` indices of allocation.
+pub fn sorted_order<T, F: FnMut(&T, &T) -> bool>(
+    v: &[T], config: &SortConfig, less: &mut F,
+) -> Option<Vec<u32>> {
+    u32::try_from(v.len()).ok().and_then(|_| try_sorted_order(v, config, less).ok())
+}
+
+/// Return the indices of the elements of `v` in stably sorted order as [`sorted_order`] does,
+/// assuming `v` has at most `u32::MAX` elements, or the error of the allocation that failed.
+///
+/// Cost: that of [`crate::aero::sort_full`] in comparisons, and `3n / 2` indices of allocation.
+pub fn try_sorted_order<T, F: FnMut(&T, &T) -> bool>(
+    v: &[T], config: &SortConfig, less: &mut F,
+) -> Result<Vec<u32>, TryReserveError> {
+    let n = v.len();
+    debug_assert!(u32::try_from(n).is_ok());
+
+    let [mut order, mut buf] = [Vec::<u32>::new(), Vec::new()];
+    order.try_reserve_exact(n)?;
+    buf.try_reserve_exact(n / 2)?;
+
+    order.extend(0..n as u32);
+    buf.resize(n / 2, 0);
+    crate::aero::sort_full(&mut order, &mut buf, config, &mut |&i, &j| {
+        less(&v[i as usize], &v[j as usize])
+    });
+    Ok(order)
+}
+
+/// Move the element at `order[k]` to `k` for every `k`, assuming `order` is a permutation of the
+/// indices of `v`. See [`permute_columns`].
+///
+/// Cost: at most `n + n / 2` moves.
+pub fn permute<T>(v: &mut [T], order: &mut [u32]) {
+    assert_eq!(v.len(), order.len());
+    unsafe { permute_columns(v.as_mut_ptr(), order) }
+}
+
+/// Elements laid out in one or more slices of equal length, which move together as the elements of
+/// a slice of tuples would.
+pub trait Columns {
+    /// An element taken out of its place.
+    type Hole;
+
+    /// Take the element at `i` out of its place, leaving it logically uninitialized.
+    unsafe fn take(&self, i: usize) -> Self::Hole;
+
+    /// Move the element at `from` to the vacant place `to`.
+    unsafe fn shift(&self, from: usize, to: usize);
+
+    /// Put `hole` into the vacant place `i`.
+    unsafe fn put(&self, i: usize, hole: Self::Hole);
+}
+
+impl<T> Columns for *mut T {
+    type Hole = T;
+
+    unsafe fn take(&self, i: usize) -> T {
+        ptr::read(self.add(i))
+    }
+
+    unsafe fn shift(&self, from: usize, to: usize) {
+        ptr::copy_nonoverlapping(self.add(from), self.add(to), 1);
+    }
+
+    unsafe fn put(&self, i: usize, hole: T) {
+        ptr::write(self.add(i), hole);
+    }
+}
+
+impl<A: Columns, B: Columns> Columns for (A, B) {
+    type Hole = (A::Hole, B::Hole);
+
+    unsafe fn take(&self, i: usize) -> Self::Hole {
+        (self.0.take(i), self.1.take(i))
+    }
+
+    unsafe fn shift(&self, from: usize, to: usize) {
+        self.0.shift(from, to);
+        self.1.shift(from, to);
+    }
+
+    unsafe fn put(&self, i: usize, (a, b): Self::Hole) {
+        self.0.put(i, a);
+        self.1.put(i, b);
+    }
+}
+
+/// Move the element of `columns` at `order[k]` to `k` for every `k`. Every cycle of the permutation
+/// is followed from its start, marking each position done by pointing it to itself, so `order`
+/// ends up as the identity. Nothing here can panic while an element is out of its place.
+///
+/// Cost: at most `n + n / 2` moves per column.
+///
+/// # Safety
+///
+/// `order` is a permutation of `0..n`, and every column of `columns` is valid for `n` reads and
+/// writes.
+pub unsafe fn permute_columns(columns: impl Columns, order: &mut [u32]) {
+    for start in 0..order.len() {
+        if order[start] as usize == start {
+            continue;
+        }
+
+        let hole = columns.take(start);
+        let mut k = start;
+        loop {
+            let from = order[k] as usize;
+            order[k] = k as u32;
+            metrics::moved(1);
+            if from == start {
+                columns.put(k, hole);
+                break;
+            }
+
+            columns.shift(from, k);
+            k = from;
+        }
+    }
+}
@@ -1,31 +1,34 @@
 use core::ptr;
 
 use crate::blocks::{BlockId, Block};
+use crate::metrics;
+
+use sort_util::op;
 
 /// Scroll `count` elements starting at `s` to the left `n` times. Return the destination pointer.
 ///
-/// Cost: `O(n)` swaps.
+/// Cost: `O(n + count)` moves.
 pub unsafe fn scroll_right<T>(s: *mut T, n: usize, count: usize) -> *mut T {
-    for i in 0..(n * (count != 0) as usize) {
-        let cur = s.add(i);
-        ptr::swap_nonoverlapping(cur, cur.add(count), 1);
+    if n != 0 && count != 0 {
+        op::rotate(s, n + count, count);
+        metrics::moved(n + count);
     }
     s.add(n)
 }
 
 /// Scroll `count` elements starting at `s` to the right `n` times. Return the destination pointer.
 ///
-/// Cost: `O(n)` swaps.
+/// Cost: `O(n + count)` moves.
 pub unsafe fn scroll_left<T>(s: *mut T, n: usize, count: usize) -> *mut T {
-    for i in 1..=(n * (count != 0) as usize) {
-        let cur = s.sub(i);
-        ptr::swap_nonoverlapping(cur.add(count), cur, 1);
+    if n != 0 && count != 0 {
+        op::rotate(s.sub(n), n + count, n);
+        metrics::moved(n + count);
     }
     s.sub(n)
 }
 
 /// Merge assuming the following context:
-/// ```
+/// ```text
 ///     ........... LLLLLL RRRRRRRRRRR
 ///         epb     excess     epb
 /// ```
@@ -48,6 +51,7 @@ pub unsafe fn merge_up<T, F: FnMut(&T, &T) -> bool>(
             let right = less(&*r, &*l);
             [i, j] = [i + !right as usize, j + right as usize];
             ptr::swap_nonoverlapping(if right { r } else { l }, dst.add(i + j - 1), 1);
+            metrics::swapped(1);
         }
 
         [n - i, m - j]
@@ -69,7 +73,7 @@ pub unsafe fn merge_up<T, F: FnMut(&T, &T) -> bool>(
 }
 
 /// Merge in-place assuming the following context:
-/// ```
+/// ```text
 ///     LLLLL RRRRRRR
 ///       a      b
 /// ```
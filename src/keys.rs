@@ -1,10 +1,18 @@
-use crate::merge::{merge_down, merge_up};
+use crate::merge::merge_swapping;
 use crate::merge::{Merge, MergeUnchecked};
 
-use sort_util::op::move_slice;
 use sort_util::Sorted;
 
 /// A collection of contiguous and comparatively distinct elements, called "keys".
+///
+/// The first `tags_len = inner.len() - buffer_len` keys are tags, which mark the A-blocks of a
+/// block merge, and the last `buffer_len` form an internal buffer, through which merges swap
+/// elements. A scrolling block merge splits its runs into blocks as long as the buffer, so it can
+/// tag a left run only if it is shorter than `unsortable_left_len = (tags_len + 1) * buffer_len`
+/// elements.
+///
+/// Every merge through the keys leaves the tags sorted and the buffer above them, though it may
+/// scramble the buffer. Once merging is done, [`Keys::sort_internal_buffer`] sorts the keys again.
 pub struct Keys<'a, T> {
     /// The slice that the keys exist in.
     pub inner: &'a mut [T],
@@ -19,7 +27,19 @@ pub struct Keys<'a, T> {
 
 impl<'a, T> Keys<'a, T> {
     /// Establish a new collection of keys over `inner` with a buffer length of `buffer_len`.
-    pub fn new(inner: &'a mut [T], buffer_len: usize) -> Self {
+    ///
+    /// # Safety
+    ///
+    /// Every merge through the keys relies on the following, under the comparison function it is
+    /// given, as [`crate::state`] establishes them when collecting keys:
+    /// - `inner` is not empty, and `inner.len() <= 2 * buffer_len` and `buffer_len <= inner.len()`,
+    ///   so that the tags never outnumber the buffer. A merge that the buffer cannot take then has
+    ///   more elements than there are keys, which a block merge needs.
+    /// - The elements of `inner` are distinct, the tags are sorted, and every element of the buffer
+    ///   is greater than every tag.
+    pub unsafe fn new(inner: &'a mut [T], buffer_len: usize) -> Self {
+        debug_assert!(!inner.is_empty() && buffer_len <= inner.len());
+        debug_assert!(2 * buffer_len >= inner.len(), "tags outnumber the buffer");
         let keys_len = inner.len() - buffer_len;
         let unsortable_left_len = (keys_len + 1) * buffer_len;
         Self { inner, buffer_len, tags_len: keys_len, unsortable_left_len }
@@ -32,7 +52,14 @@ impl<T> Keys<'_, T> {
         a.len() < self.unsortable_left_len
     }
 
-    /// Sort this collection of keys.
+    /// Make every block merge through this collection of keys a rotation block merge, by making
+    /// every left run too long to tag for a scrolling block merge.
+    pub fn disable_scrolling(&mut self) {
+        self.unsortable_left_len = 0;
+    }
+
+    /// Sort this collection of keys, which only requires sorting the buffer (see
+    /// [`Keys::sort_first`]).
     pub fn sort_internal_buffer<F: FnMut(&T, &T) -> bool>(&mut self, less: &mut F) {
         self.sort_first(self.inner.len(), less);
     }
@@ -41,6 +68,34 @@ impl<T> Keys<'_, T> {
     /// by the following invariants:
     /// 1. Our buffer is partitioned to be greater than our tags
     /// 2. Our tags are always sorted
+    ///
+    /// With `len` at most the number of tags, there is nothing to sort:
+    ///
+    /// ```
+    /// # #[cfg(feature = "internal")] {
+    /// use core::cell::Cell;
+    ///
+    /// // 2 tags, then a buffer of 3 keys that merges have scrambled
+    /// let mut inner = [1, 2, 5, 4, 3];
+    /// let mut keys = unsafe { aerosort::Keys::new(&mut inner, 3) };
+    /// let comparisons = Cell::new(0);
+    /// let mut less = |x: &i32, y: &i32| { comparisons.set(comparisons.get() + 1); x < y };
+    ///
+    /// keys.sort_first(0, &mut less);
+    /// keys.sort_first(2, &mut less);
+    /// assert_eq!(keys.inner, [1, 2, 5, 4, 3]);
+    /// assert_eq!(comparisons.get(), 0);
+    ///
+    /// keys.sort_first(4, &mut less);
+    /// assert_eq!(keys.inner, [1, 2, 4, 5, 3]);
+    /// keys.sort_internal_buffer(&mut less);
+    /// assert_eq!(keys.inner, [1, 2, 3, 4, 5]);
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` exceeds the number of keys.
     pub fn sort_first<F: FnMut(&T, &T) -> bool>(&mut self, len: usize, less: &mut F) {
         let tags_len = self.tags_len;
         crate::mini::heap_sort(&mut self.inner[tags_len..len.max(tags_len)], less);
@@ -67,12 +122,7 @@ impl<T> Keys<'_, T> {
             return Sorted::Fail;
         }
 
-        if a.len() == shorter_side {
-            merge_up::<_, true>([unsafe { move_slice::<_, true>(self.buffer(), a) }, b], less);
-        } else {
-            merge_down::<_, true>([a, unsafe { move_slice::<_, true>(self.buffer(), b) }], less);
-        }
-
+        unsafe { merge_swapping([a, b], self.buffer(), less); }
         Sorted::Done
     }
 }
@@ -80,7 +130,7 @@ impl<T> Keys<'_, T> {
 impl<T> Merge<T> for Keys<'_, T> {
     /// Return `true` iff this key collection has at least one key.
     fn can_merge(&self, _: [&mut [T]; 2]) -> bool {
-        // When we call this, we will have collected at least one key. At least, we better have.
+        // `Keys::new` requires at least one key
         !self.inner.is_empty() || unsafe { core::hint::unreachable_unchecked() }
     }
 }
@@ -89,8 +139,11 @@ impl<T> MergeUnchecked<T> for Keys<'_, T> {
     /// Perform either an internal regular merge or a block merge.
     ///
     /// Cost: `O(n + m)` comparisons and `O(n + m)` moves, given key collection was done properly.
-    fn merge_unchecked<F: FnMut(&T, &T) -> bool>(&mut self, [a, b]: [&mut [T]; 2], less: &mut F) {
+    unsafe fn merge_unchecked<F: FnMut(&T, &T) -> bool>(
+        &mut self, [a, b]: [&mut [T]; 2], less: &mut F,
+    ) {
+        // Both runs are longer than the buffer if it cannot take them
         self.merge_basic([a, b], less)
-            .or(|| crate::blocks::block_merge(self, [a, b], less));
+            .or(|| unsafe { crate::blocks::block_merge(self, [a, b], less) });
     }
 }
@@ -4,10 +4,12 @@
 //! aerosort is a sorting library. It is comparison-based, stable, and in-place by default. The
 //! following interface is provided:
 //!
-//! | Family         | Heap allocation (elements) |
-//! |----------------|----------------------------|
-//! | [`sort`]       | none                       |
-//! | [`sort_with`]  | given (variable)           |
+//! | Family         | Heap allocation                                                       |
+//! |----------------|-----------------------------------------------------------------------|
+//! | [`sort`]       | none                                                                  |
+//! | [`sort_with`]  | given (variable)                                                      |
+//! | `sort_alloc`   | `n / 2` elements, or `3n / 2` indices for large `T` (`alloc` feature) |
+//! | `par_sort`     | `n` elements (`parallel` feature)                                     |
 //!
 //! To sort using a comparator, use the `_by` extension and pass a comparison function e.g.
 //! [`sort_by`]`(&mut v, cmp)`. This allows you to sort descending and into other desired patterns.
@@ -15,14 +17,216 @@
 //! To sort by key, use the `_by_key` interface and pass a mapping e.g. [`sort_by_key`]
 //! `(&mut v, f)`. This will sort ascending by key (lowest keys first).
 //!
-//! The worst-case time complexity is always `O(n log n)` across all external space sizes.
+//! To build comparison functions from several keys, see the [`cmp`] module.
+//!
+//! With the `metrics` feature, `last_sort_stats` reports the comparisons and moves performed by
+//! the most recent sort. With the `trace` feature, `trace_sorts` reports the decisions sorts make.
+//!
+//! To tune how a sort trades comparisons for moves, use a [`SortConfig`].
+//!
+//! To sort many slices with the same external buffer, use a [`Scratch`] buffer.
+//!
+//! To combine a buffer, a configuration, and a comparison function or key, use a [`SortBuilder`].
+//!
+//! With the `std` feature, the `external_sort` module sorts records that do not fit in memory.
+//!
+//! With the `testing` feature, the `testing` module generates inputs that exercise each path of a
+//! sort, for tests of code built on aerosort.
+//!
+//! With the `verify-stable` feature, the `verify` module sorts while checking its own stability,
+//! panicking if equal elements end up out of their original order. Other sorts are unaffected.
+//!
+//! The worst-case time complexity is always `O(n log n)` across all external space sizes. This
+//! includes input with too few distinct values to merge with keys, which a sort without a buffer
+//! distributes among them with rotations; arrangements of 12 values that make those rotations long
+//! still take a small multiple of `n log n` comparisons and moves:
+//!
+//! ```
+//! # #[cfg(feature = "metrics")] {
+//! let n: usize = 1 << 16;
+//! let arrangements: [fn(usize) -> usize; 4] = [
+//!     |i| (i as u64 * 0x9e37_79b9 >> 7) as usize % 12,    // random
+//!     |i| 11 - i * 12 / (1 << 16),                        // descending
+//!     |i| i % 12,                                         // interleaved
+//!     |i| i / 100 % 12,                                   // sawtooth
+//! ];
+//!
+//! for key in arrangements {
+//!     let mut v: Vec<(usize, usize)> = (0..n).map(|i| (key(i), i)).collect();
+//!     aerosort::sort_by_key(&mut v, |x| x.0);
+//!     assert!(v.windows(2).all(|w| w[0] < w[1]));
+//!
+//!     // `n log n` is `16n` here
+//!     let stats = aerosort::last_sort_stats();
+//!     assert!(stats.comparisons < 24 * n && stats.moves < 64 * n, "{stats:?}");
+//! }
+//! # }
+//! ```
+//!
+//! Every slice that fits in memory can be sorted, on 32- and 64-bit targets alike: the index
+//! arithmetic of a sort is exact for every `n <= isize::MAX`, widening to `u128` where a product of
+//! two lengths could overflow `usize`.
+//!
+//! Every sort is stable unless its name or its documentation says otherwise, as for
+//! [`sort_unstable`] and [`heap_sort`], whichever strategy the length, the buffer, and the number
+//! of distinct values lead it to use. The following checks each of those strategies, and with the
+//! `trace` feature, that each of them was taken:
+//!
+//! ```
+//! let sort_all = || {
+//!     for n in [10, 80, 10_000] {
+//!         for distinct in [1, 5, 40, n] {
+//!             for ext in [0, n / 8, n / 2] {
+//!                 let mut v: Vec<_> = (0..n).map(|i| (i * 7919 % distinct, i)).collect();
+//!                 aerosort::sort_with_by_key(&mut v, &mut vec![(0, 0); ext][..], |x| x.0);
+//!                 assert!(v.windows(2).all(|w| w[0] < w[1]));
+//!             }
+//!         }
+//!     }
+//! };
+//!
+//! # #[cfg(not(feature = "trace"))]
+//! sort_all();
+//! # #[cfg(feature = "trace")] {
+//! let mut lines = Vec::new();
+//! aerosort::trace_sorts(|line| lines.push(line.to_string()), sort_all);
+//! for strategy in ["small", "constant", "easy", "single", "distribute", "lazy", "keys"] {
+//!     let prefix = format!("sort_full: {strategy} ");
+//!     assert!(lines.iter().any(|line| line.starts_with(&prefix)), "{strategy} not taken");
+//! }
+//! # }
+//! ```
+//!
+//! If the comparison function panics, the slice being sorted is left holding all of its original
+//! elements in an unspecified order, and no element is dropped twice. Merges into an external
+//! buffer are guarded so that every element copied out is written back during unwinding; all other
+//! steps only ever swap or rotate elements.
+//!
+//! ```
+//! use std::cell::Cell;
+//! use std::mem::MaybeUninit;
+//! use std::panic::{catch_unwind, AssertUnwindSafe};
+//! use aerosort::{Scratch, SortConfig};
+//!
+//! // An element that records being dropped, and panics if it is dropped twice
+//! struct Tracked<'a>(u32, usize, &'a [Cell<bool>]);
+//!
+//! impl Drop for Tracked<'_> {
+//!     fn drop(&mut self) {
+//!         assert!(!self.2[self.1].replace(true), "element {} dropped twice", self.1);
+//!     }
+//! }
+//!
+//! let n = 2000;
+//! for sort in ["sort", "sort_with", "natural_runs"] {
+//!     for k in [0, 10, 1000, 10_000] {
+//!         let dropped: Vec<_> = (0..n).map(|_| Cell::new(false)).collect();
+//!         let key = |i| i as u32 * 7919 % 1000;
+//!         let mut v: Vec<_> = (0..n).map(|i| Tracked(key(i), i, &dropped)).collect();
+//!         let mut buf: Vec<MaybeUninit<_>> = (0..n / 4).map(|_| MaybeUninit::uninit()).collect();
+//!
+//!         // Panic at the `k`-th comparison
+//!         let mut calls = 0;
+//!         let mut cmp = |x: &Tracked, y: &Tracked| {
+//!             calls += 1;
+//!             assert!(calls <= k, "comparison {k}");
+//!             x.0.cmp(&y.0)
+//!         };
+//!
+//!         let result = catch_unwind(AssertUnwindSafe(|| match sort {
+//!             "sort" => aerosort::sort_by(&mut v, &mut cmp),
+//!             "sort_with" => {
+//!                 aerosort::sort_with_by(&mut v, Scratch::from_uninit(&mut buf), &mut cmp)
+//!             }
+//!             _ => SortConfig::new().natural_runs(true).sort_by(&mut v, &mut cmp),
+//!         }));
+//!         assert!(result.is_err());
+//!
+//!         // Every element is still there, and none has been dropped
+//!         let mut ids: Vec<_> = v.iter().map(|x| x.1).collect();
+//!         ids.sort();
+//!         assert!(ids.into_iter().eq(0..n));
+//!         assert!(!dropped.iter().any(Cell::get));
+//!
+//!         drop(v);
+//!         assert!(dropped.iter().all(Cell::get));
+//!     }
+//! }
+//! ```
+//!
+//! An inconsistent comparison function can only leave a slice unsorted, never lose or duplicate
+//! its elements. Use [`sort_checked_total`] to detect that. There is no panic-free mode: with debug
+//! assertions enabled, a sort checks its own invariants, and functions that can panic otherwise say
+//! so under "Panics".
+//!
+//! Elements are only ever moved whole, by copies and swaps of their bytes, and whenever the
+//! comparison function sees an element, that element lives in exactly one place: a merge that
+//! copies a run into an external buffer compares the copy, and writes the copy back. So elements
+//! with interior mutability, such as `Cell`s, can be sorted by what they hold, and changes the
+//! comparison function makes through them are never lost:
+//!
+//! ```
+//! use std::cell::Cell;
+//! use std::mem::MaybeUninit;
+//!
+//! let mut v: Vec<Cell<u32>> = [3, 1, 2].map(Cell::new).into();
+//! aerosort::sort_by_key(&mut v, Cell::get);
+//! assert_eq!(v.iter().map(Cell::get).collect::<Vec<_>>(), [1, 2, 3]);
+//!
+//! // Count the comparisons each element takes part in, with every kind of merge
+//! for ext in [0, 100, 500] {
+//!     let mut v: Vec<_> = (0..1000).map(|i| (Cell::new(0), i * 7919 % 100)).collect();
+//!     let mut comparisons = 0;
+//!     let mut buf: Vec<_> = (0..ext).map(|_| MaybeUninit::uninit()).collect();
+//!     aerosort::sort_with_by(&mut v, &mut buf[..], |x, y| {
+//!         [x, y].map(|e| e.0.set(e.0.get() + 1));
+//!         comparisons += 1;
+//!         x.1.cmp(&y.1)
+//!     });
+//!     assert_eq!(v.iter().map(|e| e.0.get()).sum::<u32>(), 2 * comparisons);
+//! }
+//! ```
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod cmp;
+#[cfg(feature = "std")]
+pub mod external_sort;
 
 mod aero;
-mod blocks;
+mod builder;
+mod config;
+#[cfg(feature = "alloc")]
+mod indirect;
 mod internal;
 mod keys;
 mod merge;
+mod metrics;
 mod mini;
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "radix")]
+mod radix;
+mod runs;
+mod scratch;
+#[cfg(feature = "x86-simd")]
+mod simd;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod trace;
+#[cfg(feature = "verify-stable")]
+pub mod verify;
+
+#[cfg(not(feature = "internal"))]
+mod blocks;
+
+#[cfg(feature = "internal")]
+/// Module that exposes the block merge strategies.
+pub mod blocks;
 
 #[cfg(not(feature = "internal"))]
 mod state;
@@ -32,56 +236,1957 @@ mod state;
 pub mod state;
 
 #[cfg(feature = "internal")]
-pub use aero::merge_regular;
+pub use aero::{merge_regular, sort_with_strategy, SortStrategy};
+
+#[cfg(feature = "internal")]
+pub use keys::Keys;
+
+#[cfg(feature = "internal")]
+pub use merge::{Merge, MergeUnchecked};
+
+pub use builder::{Natural, SortBuilder, SortOrder};
+pub use config::SortConfig;
+pub use mini::{BaseSort, BinaryInsertionSort, InsertionSort, Primitive};
+pub use scratch::{Buffer, Scratch};
+
+#[cfg(feature = "metrics")]
+pub use metrics::{last_sort_stats, SortStats};
+
+#[cfg(feature = "trace")]
+pub use trace::trace_sorts;
 
 use core::cmp::Ordering;
 
-use sort_util::buffer::{self, AsSliceMut};
+use sort_util::buffer;
 
 /// Sort `v`.
+///
+/// Slices of `u8` or `bool` can be sorted in linear time with [`sort_primitive`], which counts
+/// their values.
+///
+/// A slice of at most 12 distinct values is distributed among them directly, which takes `O(n)`
+/// comparisons and `O(n log n)` moves without an external buffer:
+///
+/// ```
+/// # #[cfg(feature = "metrics")] {
+/// let n = 1_000_000;
+/// let mut v: Vec<(u64, u32)> = (0..n).map(|i| ((i as u64 * 0x9e37_79b9 >> 7) % 5, i)).collect();
+/// aerosort::sort_by_key(&mut v, |x| x.0);
+/// assert!(v.windows(2).all(|w| w[0] < w[1]));
+///
+/// let checks = if cfg!(feature = "check-order") { 1 } else { 0 };
+/// let stats = aerosort::last_sort_stats();
+/// assert!(stats.comparisons < (7 + checks) * n as usize, "{stats:?}");
+/// # }
+/// ```
 #[inline(always)]
 pub fn sort<T: Ord>(v: &mut [T]) {
     sort_by(v, &mut T::cmp)
 }
 
 /// Sort `v` with a comparison function `cmp`.
+///
+/// A mutable reference to a comparison function is a comparison function too, so a stateful one
+/// can be passed as `&mut cmp` and reused across sorts:
+///
+/// ```
+/// let mut comparisons = 0;
+/// let mut cmp = |x: &i32, y: &i32| { comparisons += 1; x.cmp(y) };
+///
+/// let [mut a, mut b] = [[3, 1, 2], [6, 5, 4]];
+/// aerosort::sort_by(&mut a, &mut cmp);
+/// aerosort::sort_by(&mut b, &mut cmp);
+/// assert_eq!((a, b), ([1, 2, 3], [4, 5, 6]));
+/// assert!(comparisons >= 4);
+/// ```
 #[inline(always)]
 pub fn sort_by<T>(v: &mut [T], cmp: impl FnMut(&T, &T) -> Ordering) {
     sort_with_by(v, buffer::create(0), cmp)
 }
 
+/// Sort `v` with a comparison function `cmp` that is also given mutable access to `ctx`, such as a
+/// cache or an interner shared across comparisons. `ctx` stays borrowed only for the sort, so it
+/// can be inspected or reused afterwards.
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// // Count the steps of the Collatz sequence from `x`, remembering every count computed
+/// fn steps(cache: &mut HashMap<u64, u32>, x: u64) -> u32 {
+///     if x <= 1 {
+///         return 0;
+///     }
+///     if let Some(&s) = cache.get(&x) {
+///         return s;
+///     }
+///     let s = 1 + steps(cache, if x % 2 == 0 { x / 2 } else { 3 * x + 1 });
+///     cache.insert(x, s);
+///     s
+/// }
+///
+/// let mut v: Vec<u64> = (1..=100).rev().collect();
+/// let mut cache = HashMap::new();
+/// aerosort::sort_by_context(&mut v, &mut cache, |cache, &x, &y| {
+///     steps(cache, x).cmp(&steps(cache, y))
+/// });
+///
+/// assert_eq!(v[..3], [1, 2, 4]);
+/// assert!(v.windows(2).all(|w| cache.get(&w[0]) <= cache.get(&w[1])));
+/// ```
+#[inline(always)]
+pub fn sort_by_context<T, C: ?Sized>(
+    v: &mut [T], ctx: &mut C, mut cmp: impl FnMut(&mut C, &T, &T) -> Ordering,
+) {
+    sort_by(v, |x, y| cmp(ctx, x, y))
+}
+
 /// Sort `v` with a mapping `f` from elements to keys.
+///
+/// `f` is called twice per comparison, so a key that is expensive to build, such as a `String`, is
+/// built `O(n log n)` times. Borrow it with [`sort_by_key_ref`] if it is a field of the element, or
+/// compute each key once with [`sort_by_cached_key_with`].
+///
+/// The key type `K` cannot depend on the lifetime of the element passed to `f`, so `f` cannot
+/// return a borrow of the element, such as `|x| &x.name`; use [`sort_by_key_ref`] for that.
 #[inline(always)]
 pub fn sort_by_key<T, K: Ord>(v: &mut [T], f: impl FnMut(&T) -> K) {
     sort_with_by_key(v, buffer::create(0), f)
 }
 
+/// Sort `v` with a mapping `f` from elements to keys borrowed from them. Each key lives as long as
+/// the borrow of its element, so nothing is cloned, and `f` is called twice per comparison.
+///
+/// ```
+/// struct User { name: String, id: u32 }
+///
+/// let users = [("bob", 1), ("alice", 2), ("bob", 0)];
+/// let mut v = users.map(|(name, id)| User { name: name.into(), id });
+/// aerosort::sort_by_key_ref(&mut v, |user| user.name.as_str());
+/// assert_eq!(v.map(|user| user.id), [2, 1, 0]);
+/// ```
+#[inline(always)]
+pub fn sort_by_key_ref<T, K: ?Sized + Ord>(v: &mut [T], mut f: impl FnMut(&T) -> &K) {
+    sort_general(v, &mut [], &mut |x, y| f(x).lt(f(y)))
+}
+
+/// Sort `v` and return `true` iff it was already sorted, in which case it is left untouched.
+///
+/// ```
+/// let mut v = [1, 3, 2];
+/// assert!(!aerosort::sort_checked(&mut v));
+/// assert!(aerosort::sort_checked(&mut v));
+/// ```
+#[inline(always)]
+pub fn sort_checked<T: Ord>(v: &mut [T]) -> bool {
+    sort_checked_by(v, T::cmp)
+}
+
+/// Sort `v` with a comparison function `cmp` and return `true` iff it was already sorted. See
+/// [`sort_checked`].
+///
+/// The check stops at the first pair of elements out of order, and the sorted prefix it found is
+/// kept as a run: only the rest of `v` is sorted, then merged into it in place. The check is then
+/// no extra pass, and a sorted slice with a few elements appended is sorted in around `n`
+/// comparisons:
+///
+/// ```
+/// let mut v: Vec<u32> = (0..10_000).chain([5, 500, 5000]).collect();
+/// let mut comparisons = 0;
+/// assert!(!aerosort::sort_checked_by(&mut v, |x, y| { comparisons += 1; x.cmp(y) }));
+/// assert!(v.windows(2).all(|w| w[0] <= w[1]));
+/// if !cfg!(feature = "check-order") {
+///     assert!(comparisons < 10_100);
+/// }
+/// ```
+#[inline(always)]
+pub fn sort_checked_by<T>(v: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering) -> bool {
+    let sorted = is_sorted_until_by(v, &mut cmp);
+    if sorted == v.len() {
+        return true;
+    }
+
+    let (a, b) = v.split_at_mut(sorted);
+    sort_by(b, &mut cmp);
+    merge::merge_in_place([a, b], &mut |x, y| { metrics::compared(); cmp(x, y) == Ordering::Less });
+    false
+}
+
+/// Return `true` iff `v` is sorted, i.e. no element is less than the one before it.
+///
+/// ```
+/// assert!(aerosort::is_sorted(&[1, 2, 2, 3]));
+/// assert!(!aerosort::is_sorted(&[1, 3, 2]));
+/// ```
+#[inline(always)]
+pub fn is_sorted<T: Ord>(v: &[T]) -> bool {
+    is_sorted_by(v, T::cmp)
+}
+
+/// Return `true` iff `v` is sorted with a comparison function `cmp`. See [`is_sorted`].
+#[inline(always)]
+pub fn is_sorted_by<T>(v: &[T], cmp: impl FnMut(&T, &T) -> Ordering) -> bool {
+    is_sorted_until_by(v, cmp) == v.len()
+}
+
+/// Return the length of the longest sorted prefix of `v`. The scan stops at the first element that
+/// is less than the one before it, so this costs one comparison per element of the prefix.
+///
+/// ```
+/// assert_eq!(aerosort::is_sorted_until(&[1, 2, 2, 1, 3]), 3);
+/// assert_eq!(aerosort::is_sorted_until::<i32>(&[]), 0);
+/// ```
+#[inline(always)]
+pub fn is_sorted_until<T: Ord>(v: &[T]) -> usize {
+    is_sorted_until_by(v, T::cmp)
+}
+
+/// Return the length of the longest sorted prefix of `v` with a comparison function `cmp`. See
+/// [`is_sorted_until`].
+#[inline(always)]
+pub fn is_sorted_until_by<T>(v: &[T], mut cmp: impl FnMut(&T, &T) -> Ordering) -> usize {
+    runs::sorted_prefix(v, &mut |x, y| cmp(x, y) == Ordering::Less)
+}
+
+/// Sort `v`, calling `cancel` once per level of merges and stopping as soon as it returns `true`.
+/// Return `true` iff the sort finished. A cancelled sort leaves `v` holding all of its original
+/// elements in an unspecified order.
+///
+/// `cancel` is called before sorting, then each time the sorted run at the front of `v` is about to
+/// double in length, so `O(log n)` times in all. Its overhead is negligible, but the sort may run
+/// for up to half of its total time after the last call.
+///
+/// ```
+/// let mut v: Vec<u32> = (0..1 << 16).rev().collect();
+/// let mut calls = 0;
+/// assert!(aerosort::sort_cancellable(&mut v.clone(), || { calls += 1; false }));
+/// assert!(calls <= 16);
+///
+/// let mut calls = 0;
+/// assert!(!aerosort::sort_cancellable(&mut v, || { calls += 1; calls > 8 }));
+///
+/// v.sort();
+/// assert!(v.iter().copied().eq(0..1 << 16));
+/// ```
+#[inline(always)]
+pub fn sort_cancellable<T: Ord>(v: &mut [T], cancel: impl FnMut() -> bool) -> bool {
+    sort_cancellable_by(v, T::cmp, cancel)
+}
+
+/// Sort `v` with a comparison function `cmp`, calling `cancel` once per level of merges and
+/// stopping as soon as it returns `true`. Return `true` iff the sort finished. See
+/// [`sort_cancellable`].
+#[inline(always)]
+pub fn sort_cancellable_by<T>(
+    v: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering, mut cancel: impl FnMut() -> bool,
+) -> bool {
+    metrics::reset();
+
+    // Skip zero-sized types
+    let (config, base) = (&SortConfig::DEFAULT, &mut InsertionSort);
+    let less = &mut |x: &T, y: &T| { metrics::compared(); cmp(x, y) == Ordering::Less };
+    core::mem::size_of::<T>() == 0
+        || aero::sort_cancellable(v, &mut [], config, base, less, &mut cancel)
+}
+
+/// Sort `v`, sorting the short pieces that it merges, and `v` itself if it is too short to merge,
+/// with `base` rather than insertion sort. Pieces have at most 64 elements. This lets a sorting
+/// stable. This panics if `base` leaves a slice unsorted; checking takes at most `n` comparisons.
+/// stable. This panics if `base` leaves a slice unsorted, which costs `n` comparisons at most.
+///
+/// ```
+/// use aerosort::BaseSort;
+///
+/// // Counts the elements it sorts, delegating to the default insertion sort
+/// struct Counting(usize);
+///
+/// impl<T> BaseSort<T> for Counting {
+///     fn sort<F: FnMut(&T, &T) -> bool>(&mut self, v: &mut [T], less: &mut F) {
+///         self.0 += v.len();
+///         aerosort::InsertionSort.sort(v, less);
+///     }
+/// }
+///
+/// let mut base = Counting(0);
+/// let mut v: Vec<u32> = (0..1000).map(|i| i * 7919 % 1009).collect();
+/// aerosort::sort_with_base(&mut v, &mut base);
+/// assert!(v.windows(2).all(|w| w[0] <= w[1]));
+/// assert!(base.0 > 0);
+/// ```
+#[inline(always)]
+pub fn sort_with_base<T: Ord>(v: &mut [T], base: impl BaseSort<T>) {
+    sort_with_base_by(v, base, T::cmp)
+}
+
+/// Sort `v` with a comparison function `cmp`, sorting short pieces with `base`. See
+/// [`sort_with_base`].
+#[inline(always)]
+pub fn sort_with_base_by<T>(
+    v: &mut [T], base: impl BaseSort<T>, mut cmp: impl FnMut(&T, &T) -> Ordering,
+) {
+    metrics::reset();
+
+    // Skip zero-sized types
+    let less = &mut |x: &T, y: &T| { metrics::compared(); cmp(x, y) == Ordering::Less };
+    if core::mem::size_of::<T>() != 0 {
+        let base = &mut mini::Checked(base);
+        aero::sort_cancellable(v, &mut [], &SortConfig::DEFAULT, base, less, &mut || false);
+    }
+}
+
+/// Sort `v` in descending order. Equal elements keep their original order.
+#[inline(always)]
+pub fn sort_desc<T: Ord>(v: &mut [T]) {
+    sort_general(v, &mut [], &mut |x, y| y.lt(x))
+}
+
+/// Sort `v` in descending order with a mapping `f` from elements to keys (highest keys first).
+/// Elements with equal keys keep their original order.
+///
+/// ```
+/// let mut v = [(1, 'a'), (3, 'b'), (1, 'c'), (2, 'd')];
+/// aerosort::sort_desc_by_key(&mut v, |x| x.0);
+/// assert_eq!(v, [(3, 'b'), (2, 'd'), (1, 'a'), (1, 'c')]);
+/// ```
+#[inline(always)]
+pub fn sort_desc_by_key<T, K: Ord>(v: &mut [T], mut f: impl FnMut(&T) -> K) {
+    sort_general(v, &mut [], &mut |x, y| f(y).lt(&f(x)))
+}
+
+/// Sort `v` with a mapping `f` from elements to keys, calling `f` exactly once per element. The
+/// keys are cached in `buf` alongside the original index of each element, so `buf` must hold at
+/// least `v.len()` pairs; it does not need to be initialized. Use this when `f` is expensive.
+///
+/// ```
+/// use core::mem::MaybeUninit;
+///
+/// let mut v = ["ccc", "a", "bb"];
+/// let mut buf = [const { MaybeUninit::uninit() }; 3];
+/// aerosort::sort_by_cached_key_with(&mut v, &mut buf, |s| s.len());
+/// assert_eq!(v, ["a", "bb", "ccc"]);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `buf` is shorter than `v`. If `f` or the comparison of keys panics, `v` holds all of
+/// its original elements in an unspecified order, and the keys computed so far are dropped.
+pub fn sort_by_cached_key_with<T, K: Ord>(
+    v: &mut [T], buf: &mut [core::mem::MaybeUninit<(K, usize)>], mut f: impl FnMut(&T) -> K,
+) {
+    // Drops the keys written so far
+    struct Cached<'a, K>(&'a mut [core::mem::MaybeUninit<(K, usize)>], usize);
+
+    impl<K> core::ops::Drop for Cached<'_, K> {
+        fn drop(&mut self) {
+            let keys = self.0.as_mut_ptr().cast::<(K, usize)>();
+            unsafe { core::ptr::drop_in_place(core::ptr::slice_from_raw_parts_mut(keys, self.1)); }
+        }
+    }
+
+    let n = v.len();
+    assert!(buf.len() >= n, "key buffer is shorter than the slice");
+
+    let mut cached = Cached(buf, 0);
+    for (i, x) in v.iter().enumerate() {
+        cached.0[i].write((f(x), i));
+        cached.1 += 1;
+    }
+
+    // Sort the keys along with their indices, then move each element to its sorted position,
+    // following indices that were already moved
+    let keys = unsafe { core::slice::from_raw_parts_mut(cached.0.as_mut_ptr().cast(), n) };
+    sort_general(keys, &mut [], &mut |x: &(K, usize), y: &(K, usize)| x.0.lt(&y.0));
+
+    for i in 0..n {
+        let mut index = keys[i].1;
+        while index < i {
+            index = keys[index].1;
+        }
+        keys[i].1 = index;
+        v.swap(i, index);
+    }
+}
+
+/// Stably sort `v` with a mapping `f` from elements to byte keys. Without an external buffer, a
+/// stable counting sort is not possible in linear time, so this sorts by comparing keys; see
+/// [`sort_with_by_u8_key`].
+#[inline(always)]
+pub fn sort_by_u8_key<T>(v: &mut [T], f: impl FnMut(&T) -> u8) {
+    sort_with_by_u8_key(v, buffer::create(0), f)
+}
+
+/// Stably sort `v` with an external buffer `ext` and a mapping `f` from elements to byte keys. If
+/// `ext` can hold all of `v`, this performs a counting sort with a histogram on the stack, calling
+/// `f` exactly twice per element and moving each element twice. Otherwise, it falls back to
+/// [`sort_with_by_key`].
+///
+/// ```
+/// let mut v = [(2, 'a'), (0, 'b'), (2, 'c'), (1, 'd')];
+/// let mut buf = [(0, ' '); 4];
+/// aerosort::sort_with_by_u8_key(&mut v, &mut buf[..], |x| x.0);
+/// assert_eq!(v, [(0, 'b'), (1, 'd'), (2, 'a'), (2, 'c')]);
+/// ```
+#[inline(always)]
+pub fn sort_with_by_u8_key<T>(
+    v: &mut [T], mut ext: impl Buffer<T>, mut f: impl FnMut(&T) -> u8,
+) {
+    let ext = scratch::slots(&mut ext);
+    if ext.len() >= v.len() {
+        mini::counting_sort(v, ext, &mut f)
+    } else {
+        sort_general(v, ext, &mut |x, y| f(x) < f(y))
+    }
+}
+
 /// Sort `v` with an external buffer `ext`.
+///
+/// A buffer of any length can be passed. Each merge uses `ext` if it can hold the shorter of the
+/// two runs being merged, and the internal strategies otherwise; every run merged has at least 8
+/// elements, so a buffer shorter than that behaves exactly like an empty one. A buffer of at least
+/// `v.len() / 2` elements is used for every merge, so no keys are collected.
+///
+/// A slice whose elements are all equal is left as it is after `O(n)` comparisons, whatever the
+/// buffer:
+///
+/// ```
+/// # #[cfg(feature = "metrics")] {
+/// let n = 1 << 16;
+/// for ext in [0, n / 2] {
+///     let mut v: Vec<(u8, usize)> = (0..n).map(|i| (7, i)).collect();
+///     aerosort::sort_with_by_key(&mut v, &mut vec![(0, 0); ext][..], |x| x.0);
+///     assert!(v.iter().map(|x| x.1).eq(0..n));
+///
+///     let checks = if cfg!(feature = "check-order") { n } else { 0 };
+///     assert!(aerosort::last_sort_stats().comparisons < 3 * n + checks);
+/// }
+/// # }
+/// ```
+///
+/// Merges address `ext` as a single contiguous slice. If your memory can only be exposed in
+/// windows of a fixed length, pass one window: merges too long for it fall back to keys, so every
+/// merge still takes `O(n)` comparisons and moves whatever the window length.
+///
+/// Even a buffer of `sqrt(n) / 2` elements closes much of the gap to a full one: it takes every
+/// merge of short runs, leaving the keys only the longest few levels of merges.
+///
+/// ```
+/// # #[cfg(feature = "metrics")] {
+/// let scatter = |i: u64| i.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 20;
+/// for mask in [15, u64::MAX] {
+///     let input: Vec<u64> = (0..1 << 16).map(|i| scatter(i) & mask).collect();
+///     let mut moves = |ext: usize| {
+///         let mut v = input.clone();
+///         aerosort::sort_with(&mut v, &mut vec![0; ext][..]);
+///         assert!(v.windows(2).all(|w| w[0] <= w[1]));
+///         aerosort::last_sort_stats().moves
+///     };
+///
+///     assert!(moves(128) < moves(0));
+/// }
+/// # }
+/// ```
+///
+/// With at most 12 distinct values, a sort distributes `v` among them directly, which takes `O(n)`
+/// comparisons (see [`sort`]); a buffer of `v.len() / 32` elements makes that `O(n)` moves too:
+///
+/// ```
+/// # #[cfg(feature = "metrics")] {
+/// let n = 1_000_000;
+/// let mut v: Vec<(u64, u32)> = (0..n).map(|i| ((i as u64 * 0x9e37_79b9 >> 7) % 5, i)).collect();
+/// aerosort::sort_with_by_key(&mut v, &mut vec![(0, 0); n as usize / 32][..], |x| x.0);
+/// assert!(v.windows(2).all(|w| w[0] < w[1]));
+///
+/// // The self-check of the `check-order` feature adds a pass
+/// let checks = if cfg!(feature = "check-order") { 2 } else { 0 };
+/// let stats = aerosort::last_sort_stats();
+/// assert!(stats.comparisons < (7 + checks) * n as usize, "{stats:?}");
+/// assert!(stats.moves < (11 + checks) * n as usize, "{stats:?}");
+/// # }
+/// ```
 #[inline(always)]
-pub fn sort_with<T: Ord>(v: &mut [T], ext: impl AsSliceMut<T>) {
+pub fn sort_with<T: Ord>(v: &mut [T], ext: impl Buffer<T>) {
     sort_with_by(v, ext, &mut T::cmp)
 }
 
 /// Sort `v` with an external buffer `ext` and a comparison function `cmp`.
 #[inline(always)]
 pub fn sort_with_by<T>(
-    v: &mut [T], mut ext: impl AsSliceMut<T>, mut cmp: impl FnMut(&T, &T) -> Ordering,
+    v: &mut [T], mut ext: impl Buffer<T>, mut cmp: impl FnMut(&T, &T) -> Ordering,
 ) {
-    sort_general(v, ext.as_slice_mut(), &mut |x, y| cmp(x, y) == Ordering::Less)
+    sort_general(v, scratch::slots(&mut ext), &mut |x, y| cmp(x, y) == Ordering::Less)
 }
 
 /// Sort `v` with an external buffer `ext` and a mapping `f` from elements to keys.
 #[inline(always)]
 pub fn sort_with_by_key<T, K: Ord>(
-    v: &mut [T], mut ext: impl AsSliceMut<T>, mut f: impl FnMut(&T) -> K,
+    v: &mut [T], mut ext: impl Buffer<T>, mut f: impl FnMut(&T) -> K,
+) {
+    sort_general(v, scratch::slots(&mut ext), &mut |x, y| f(x).lt(&f(y)))
+}
+
+/// Sort `v` with a fallible comparison function `cmp`. If `cmp` returns an error, it is not called
+/// again and the first error is returned once the sort has wound down, treating every remaining
+/// comparison as equal. In that case `v` holds all of its original elements in an unspecified
+/// order.
+///
+/// ```
+/// use core::mem::MaybeUninit;
+/// use aerosort::Scratch;
+///
+/// // Insertion sort, block merges, and merges through the external buffer alone or with keys
+/// for (n, ext) in [(30, 0), (2000, 0), (2000, 100), (2000, 1000)] {
+///     let input: Vec<String> = (0..n).map(|i| (i * 7919 % n).to_string()).collect();
+///     let mut sorted = input.clone();
+///     sorted.sort();
+///     let mut buf: Vec<MaybeUninit<String>> = (0..ext).map(|_| MaybeUninit::uninit()).collect();
+///
+///     let mut total = 0;
+///     let mut v = input.clone();
+///     let result = aerosort::sort_with_by_try(&mut v, Scratch::from_uninit(&mut buf), |x, y| {
+///         total += 1;
+///         Ok::<_, usize>(x.cmp(y))
+///     });
+///     assert_eq!((result, &v), (Ok(()), &sorted));
+///
+///     // Fail from the `k`-th comparison on, naming the comparison in the error
+///     for k in [0, 1, total / 3, total / 2, total - 1] {
+///         let mut v = input.clone();
+///         let mut calls = 0;
+///         let result = aerosort::sort_with_by_try(&mut v, Scratch::from_uninit(&mut buf), |x, y| {
+///             calls += 1;
+///             if calls > k { Err(calls - 1) } else { Ok(x.cmp(y)) }
+///         });
+///         assert_eq!((result, calls), (Err(k), k + 1));
+///
+///         // Every element is still there, once
+///         v.sort();
+///         assert_eq!(v, sorted);
+///     }
+/// }
+/// ```
+#[inline(always)]
+pub fn sort_by_try<T, E>(
+    v: &mut [T], cmp: impl FnMut(&T, &T) -> Result<Ordering, E>,
+) -> Result<(), E> {
+    sort_with_by_try(v, buffer::create(0), cmp)
+}
+
+/// Sort `v` with an external buffer `ext` and a fallible comparison function `cmp`. See
+/// [`sort_by_try`].
+#[inline(always)]
+pub fn sort_with_by_try<T, E>(
+    v: &mut [T], mut ext: impl Buffer<T>, mut cmp: impl FnMut(&T, &T) -> Result<Ordering, E>,
+) -> Result<(), E> {
+    let mut error = None;
+    let ext = scratch::slots(&mut ext);
+    sort_general(v, ext, &mut |x, y| error.is_none() && match cmp(x, y) {
+        Ok(ord) => ord == Ordering::Less,
+        Err(e) => { error = Some(e); false }
+    });
+
+    error.map_or(Ok(()), Err)
+}
+
+/// The reason a sort that reports errors, such as [`sort_checked_total`], could not sort a slice.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SortError {
+    /// The comparison function is not a total order (for example, it compares `NaN`s or its
+    /// results change between calls), and the slice was left unsorted. The slice still holds all of
+    /// its original elements.
+    InconsistentOrder,
+
+    /// Memory that the sort needs could not be allocated, which is always the case for the indices
+    /// of a slice of more than `u32::MAX` elements. The slice was left untouched.
+    AllocationFailed,
+}
+
+/// Sort `v` and return [`SortError::InconsistentOrder`] if the result is not sorted. An
+/// inconsistent comparison function can only leave `v` unsorted, since the loops of a sort are
+/// bounded by run, block and buffer lengths rather than by comparison results. This reports that
+/// case rather than leaving it to be found later.
+///
+/// ```
+/// use aerosort::SortError;
+///
+/// let mut v = [3, 1, 2];
+/// assert_eq!(aerosort::sort_checked_total(&mut v), Ok(()));
+///
+/// // Every element is less than every other
+/// let result = aerosort::sort_checked_total_by(&mut v, |_, _| core::cmp::Ordering::Less);
+/// assert_eq!(result, Err(SortError::InconsistentOrder));
+/// ```
+#[inline(always)]
+pub fn sort_checked_total<T: Ord>(v: &mut [T]) -> Result<(), SortError> {
+    sort_checked_total_by(v, T::cmp)
+}
+
+/// Sort `v` with a comparison function `cmp` and return [`SortError::InconsistentOrder`] if the
+/// result is not sorted by `cmp`. See [`sort_checked_total`].
+///
+/// The check costs `n - 1` comparisons on top of the sort. For a comparison function that reports
+/// errors itself, see [`sort_by_try`].
+#[inline(always)]
+pub fn sort_checked_total_by<T>(
+    v: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering,
+) -> Result<(), SortError> {
+    let less = &mut |x: &T, y: &T| cmp(x, y) == Ordering::Less;
+    sort_general(v, &mut [], less);
+
+    if runs::sorted_prefix(v, less) == v.len() {
+        Ok(())
+    } else {
+        Err(SortError::InconsistentOrder)
+    }
+}
+
+/// Sort `v` and move the first element of each run of equal elements to the front, in order.
+/// Return the number of such elements. As the sort is stable, the element kept from each run is
+/// the one that came first in `v`. The elements past the returned length are the removed
+/// duplicates, in an unspecified order.
+///
+/// ```
+/// let mut v = [3, 1, 3, 2, 1];
+/// let len = aerosort::sort_dedup(&mut v);
+/// assert_eq!(v[..len], [1, 2, 3]);
+/// ```
+#[inline(always)]
+pub fn sort_dedup<T: Ord>(v: &mut [T]) -> usize {
+    sort_dedup_by(v, T::cmp)
+}
+
+/// Sort `v` with a comparison function `cmp` and move the first element of each run of equal
+/// elements to the front. See [`sort_dedup`].
+#[inline(always)]
+pub fn sort_dedup_by<T>(v: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering) -> usize {
+    sort_by(v, &mut cmp);
+
+    // Compact with swaps, so the duplicates stay in `v`
+    let mut len = (!v.is_empty()) as usize;
+    for i in 1..v.len() {
+        if cmp(&v[len - 1], &v[i]) != Ordering::Equal {
+            v.swap(len, i);
+            len += 1;
+        }
+    }
+
+    len
+}
+
+/// Sort `v` and return an iterator over its groups of equal elements, in sorted order. As the sort
+/// is stable, each group holds its elements in the order they came in `v`.
+///
+/// ```
+/// let mut v = [(2, 'a'), (1, 'b'), (2, 'c'), (3, 'd'), (2, 'e')];
+/// let groups: Vec<_> = aerosort::sort_group_by(&mut v, |x, y| x.0.cmp(&y.0)).collect();
+/// assert_eq!(groups, [&[(1, 'b')][..], &[(2, 'a'), (2, 'c'), (2, 'e')], &[(3, 'd')]]);
+///
+/// let mut v: Vec<u32> = (0..10_000).map(|i| i % 3).collect();
+/// let sizes: Vec<_> = aerosort::sort_group(&mut v).map(<[u32]>::len).collect();
+/// assert_eq!(sizes, [3334, 3333, 3333]);
+/// ```
+#[inline(always)]
+pub fn sort_group<T: Ord>(v: &mut [T]) -> impl Iterator<Item = &[T]> {
+    sort_group_by(v, T::cmp)
+}
+
+/// Sort `v` with a comparison function `cmp` and return an iterator over its groups of equal
+/// elements. See [`sort_group`].
+#[inline(always)]
+pub fn sort_group_by<T>(
+    v: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering,
+) -> impl Iterator<Item = &[T]> {
+    sort_by(v, &mut cmp);
+    v.chunk_by(move |x, y| cmp(x, y) == Ordering::Equal)
+}
+
+/// Stably partition `v` so that every element satisfying `pred` comes first, and return the number
+/// of such elements. This performs `O(n log n)` moves.
+///
+/// ```
+/// let mut v = [1, 2, 3, 4, 5, 6];
+/// let split = aerosort::stable_partition(&mut v, |x| x % 2 == 0);
+/// assert_eq!((split, v), (3, [2, 4, 6, 1, 3, 5]));
+/// ```
+#[inline(always)]
+pub fn stable_partition<T>(v: &mut [T], pred: impl FnMut(&T) -> bool) -> usize {
+    stable_partition_with(v, buffer::create(0), pred)
+}
+
+/// Stably partition `v` with an external buffer `ext`. See [`stable_partition`].
+///
+/// This performs `O(n)` moves if `ext` can hold all of `v`, and `O(n log (n / m))` moves for a
+/// buffer of `m` elements otherwise.
+#[inline(always)]
+pub fn stable_partition_with<T>(
+    v: &mut [T], mut ext: impl Buffer<T>, mut pred: impl FnMut(&T) -> bool,
+) -> usize {
+    merge::partition(v, scratch::slots(&mut ext), &mut pred)
+}
+
+/// Sort the floating-point values in `v` by total order. Negative NaNs come first, followed by
+/// `-inf`, negative numbers, `-0.0`, `+0.0`, positive numbers, `+inf`, and positive NaNs.
+///
+/// ```
+/// let mut v = [1.0, f64::NAN, 0.0, -0.0, f64::NEG_INFINITY];
+/// aerosort::sort_floats(&mut v);
+/// assert_eq!(v[0], f64::NEG_INFINITY);
+/// assert!(v[1].is_sign_negative() && v[2].is_sign_positive() && v[3] == 1.0 && v[4].is_nan());
+/// ```
+#[inline(always)]
+pub fn sort_floats<F: cmp::Float>(v: &mut [F]) {
+    sort_by(v, F::total_cmp)
+}
+
+/// Sort `v` in parallel, using an allocated buffer of `n` elements.
+///
+/// Pieces of up to 8192 elements are sorted on their own, and the sorted pieces merged in parallel;
+/// equal elements keep their order across both:
+///
+/// ```
+/// let n = 100_000;
+/// let mut v: Vec<(u32, u32)> = (0..n).map(|i| (i * 7919 % 100, i)).collect();
+/// aerosort::par_sort_by_key(&mut v, |x| x.0);
+/// assert!(v.windows(2).all(|w| w[0] < w[1]));
+///
+/// # #[cfg(feature = "metrics")] {
+/// // Comparisons are counted across threads, from zero for every sort
+/// let comparisons = aerosort::last_sort_stats().comparisons;
+/// aerosort::par_sort_by_key(&mut v, |x| x.0);
+/// assert!(aerosort::last_sort_stats().comparisons < comparisons);
+/// # }
+/// ```
+#[cfg(feature = "parallel")]
+#[inline(always)]
+pub fn par_sort<T: Ord + Send>(v: &mut [T]) {
+    par_sort_by(v, T::cmp)
+}
+
+/// Sort `v` in parallel with a comparison function `cmp`, using an allocated buffer of `n`
+/// elements.
+#[cfg(feature = "parallel")]
+#[inline(always)]
+pub fn par_sort_by<T: Send>(v: &mut [T], cmp: impl Fn(&T, &T) -> Ordering + Sync) {
+    metrics::reset();
+
+    // Skip zero-sized types
+    if core::mem::size_of::<T>() != 0 {
+        parallel::par_sort(v, &|x, y| { metrics::compared(); cmp(x, y) == Ordering::Less });
+    }
+}
+
+/// Sort `v` in parallel with a mapping `f` from elements to keys, using an allocated buffer of `n`
+/// elements.
+#[cfg(feature = "parallel")]
+#[inline(always)]
+pub fn par_sort_by_key<T: Send, K: Ord>(v: &mut [T], f: impl Fn(&T) -> K + Sync) {
+    par_sort_by(v, |x, y| f(x).cmp(&f(y)))
+}
+
+/// Write the sorted contents of `src` into `dst`, leaving `src` untouched. The elements are cloned
+/// into `dst` before sorting.
+///
+/// # Panics
+///
+/// Panics if `src` and `dst` have different lengths.
+#[inline(always)]
+pub fn sort_into<T: Clone + Ord>(src: &[T], dst: &mut [T]) {
+    sort_into_by(src, dst, &mut T::cmp)
+}
+
+/// Write the sorted contents of `src` into `dst` with a comparison function `cmp`, leaving `src`
+/// untouched. The elements are cloned into `dst` before sorting.
+///
+/// # Panics
+///
+/// Panics if `src` and `dst` have different lengths.
+#[inline(always)]
+pub fn sort_into_by<T: Clone>(src: &[T], dst: &mut [T], cmp: impl FnMut(&T, &T) -> Ordering) {
+    assert_eq!(src.len(), dst.len(), "source and destination lengths differ");
+    dst.clone_from_slice(src);
+    sort_by(dst, cmp)
+}
+
+/// Move the items of `iter` into `buf` and return them sorted. If `iter` has more items than `buf`
+/// can hold, only the first `buf.len()` items are taken, and the rest are left in `iter`; pass
+/// `&mut iter` to keep them. `buf` does not need to be initialized.
+///
+/// The returned items are never dropped by `buf`, as it holds [`MaybeUninit`] values; drop them in
+/// place if they own resources. If `iter` or the comparison panics, the items taken so far are
+/// leaked.
+///
+/// ```
+/// use core::mem::MaybeUninit;
+///
+/// let mut buf = [const { MaybeUninit::uninit() }; 4];
+/// assert_eq!(aerosort::sort_from_iter([3, 1, 2], &mut buf), [1, 2, 3]);
+///
+/// // Lazy iterators are only advanced as far as `buf` has room
+/// let mut evens = (1..).map(|i| 10 - 2 * i);
+/// assert_eq!(aerosort::sort_from_iter(&mut evens, &mut buf), [2, 4, 6, 8]);
+/// assert_eq!(evens.next(), Some(0));
+/// ```
+///
+/// [`MaybeUninit`]: core::mem::MaybeUninit
+#[inline(always)]
+pub fn sort_from_iter<T: Ord>(
+    iter: impl IntoIterator<Item = T>, buf: &mut [core::mem::MaybeUninit<T>],
+) -> &mut [T] {
+    sort_from_iter_by(iter, buf, T::cmp)
+}
+
+/// Move the items of `iter` into `buf` and return them sorted with a comparison function `cmp`.
+/// See [`sort_from_iter`].
+pub fn sort_from_iter_by<T>(
+    iter: impl IntoIterator<Item = T>, buf: &mut [core::mem::MaybeUninit<T>],
+    cmp: impl FnMut(&T, &T) -> Ordering,
+) -> &mut [T] {
+    let mut len = 0;
+    for (slot, x) in buf.iter_mut().zip(iter) {
+        slot.write(x);
+        len += 1;
+    }
+
+    let v = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr().cast(), len) };
+    sort_by(v, cmp);
+    v
+}
+
+/// Clone the `k` least elements of `v` into `out` and return them sorted, leaving `v` untouched, or
+/// all of `v` if it has fewer than `k` elements. `out` does not need to be initialized.
+///
+/// This keeps the elements in a heap of `k` elements while scanning `v` once, so it takes
+/// `O(n log k)` comparisons and clones only the elements that are among the least seen so far,
+/// which suits taking the top of a long borrowed slice. Which of several equal elements are
+/// returned, and their order, is unspecified. As with [`sort_from_iter`], the returned elements
+/// are never dropped by `out`, and are leaked if a clone or the comparison panics.
+///
+/// ```
+/// use core::mem::MaybeUninit;
+///
+/// let v: Vec<u32> = (0..1000).map(|i| i * 7919 % 1009).collect();
+/// let mut sorted = v.clone();
+/// sorted.sort();
+///
+/// let mut out = [const { MaybeUninit::uninit() }; 16];
+/// for k in [0, 1, 10, 16] {
+///     assert_eq!(*aerosort::k_smallest(&v, k, &mut out), sorted[..k]);
+/// }
+///
+/// // With fewer than `k` elements, all of them are returned
+/// assert_eq!(*aerosort::k_smallest(&[3, 1, 2], 10, &mut out), [1, 2, 3]);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `out` is shorter than both `k` and `v`.
+#[inline(always)]
+pub fn k_smallest<'a, T: Ord + Clone>(
+    v: &[T], k: usize, out: &'a mut [core::mem::MaybeUninit<T>],
+) -> &'a mut [T] {
+    k_smallest_by(v, k, out, T::cmp)
+}
+
+/// Clone the `k` least elements of `v` into `out` and return them sorted with a comparison
+/// function `cmp`. See [`k_smallest`].
+#[inline(always)]
+pub fn k_smallest_by<'a, T: Clone>(
+    v: &[T], k: usize, out: &'a mut [core::mem::MaybeUninit<T>],
+    mut cmp: impl FnMut(&T, &T) -> Ordering,
+) -> &'a mut [T] {
+    let k = k.min(v.len());
+    assert!(out.len() >= k, "output is shorter than k");
+
+    metrics::reset();
+    let less = &mut |x: &T, y: &T| { metrics::compared(); cmp(x, y) == Ordering::Less };
+
+    // Start from the first `k` elements, and let the rest replace the greatest of them
+    out.iter_mut().zip(&v[..k]).for_each(|(slot, x)| { slot.write(x.clone()); });
+    let heap = unsafe { core::slice::from_raw_parts_mut(out.as_mut_ptr().cast(), k) };
+    mini::keep_least(heap, &v[k..], less);
+    heap
+}
+
+/// Sort `v` with an allocated external buffer. Elements of at least 384 bytes are sorted as
+/// [`sort_indirect`] does instead, which allocates less and moves each element about once.
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn sort_alloc<T: Ord>(v: &mut [T]) {
+    sort_alloc_by(v, T::cmp)
+}
+
+/// Sort `v` with an allocated external buffer and a comparison function `cmp`. See
+/// [`sort_alloc`].
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn sort_alloc_by<T>(v: &mut [T], cmp: impl FnMut(&T, &T) -> Ordering) {
+    if core::mem::size_of::<T>() >= indirect::MIN_SIZE {
+        sort_indirect_by(v, cmp)
+    } else {
+        Scratch::new().sort_by(v, cmp)
+    }
+}
+
+/// Sort `v` with an allocated external buffer and a mapping `f` from elements to keys. See
+/// [`sort_alloc`].
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn sort_alloc_by_key<T, K: Ord>(v: &mut [T], mut f: impl FnMut(&T) -> K) {
+    if core::mem::size_of::<T>() >= indirect::MIN_SIZE {
+        sort_indirect_by(v, |x, y| f(x).cmp(&f(y)))
+    } else {
+        Scratch::new().sort_by_key(v, f)
+    }
+}
+
+/// Sort `v` by sorting the indices of its elements, then moving every element once, straight to
+/// its place. This allocates `3n / 2` indices of 4 bytes and moves elements at most `3n / 2` times
+/// in all, so it suits elements large enough that moving them costs more than reaching them through
+/// indices to compare. [`sort_alloc`] does this for elements of at least 384 bytes.
+///
+/// ```
+/// let mut v: Vec<[u64; 64]> = (0..1000).map(|i| [i * 7919 % 1009; 64]).collect();
+/// aerosort::sort_indirect(&mut v);
+/// assert!(v.windows(2).all(|w| w[0] < w[1]));
+/// ```
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn sort_indirect<T: Ord>(v: &mut [T]) {
+    sort_indirect_by(v, T::cmp)
+}
+
+/// Sort `v` with a comparison function `cmp` by sorting the indices of its elements. See
+/// [`sort_indirect`].
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn sort_indirect_by<T>(v: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering) {
+    metrics::reset();
+
+    // Skip zero-sized types
+    if core::mem::size_of::<T>() != 0 {
+        let less = &mut |x: &T, y: &T| { metrics::compared(); cmp(x, y) == Ordering::Less };
+        indirect::sort_indirect(v, &SortConfig::DEFAULT, less);
+    }
+}
+
+/// Sort `v` with a mapping `f` from elements to keys by sorting the indices of its elements. See
+/// [`sort_indirect`].
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn sort_indirect_by_key<T, K: Ord>(v: &mut [T], mut f: impl FnMut(&T) -> K) {
+    sort_indirect_by(v, |x, y| f(x).cmp(&f(y)))
+}
+
+/// Sort `keys` and move the elements of `vals` along with them, so that every value stays with its
+/// key, as if sorting a slice of `(key, value)` pairs by key. Equal keys keep their original order.
+///
+/// The indices of `keys` are sorted first, then both slices are permuted once, with at most
+/// `3n / 2` moves per slice.
+///
+/// ```
+/// let mut keys = [3u32, 1, 2, 1];
+/// let mut vals = ["c", "a", "b", "A"].map(String::from);
+/// aerosort::sort_zipped(&mut keys, &mut vals);
+/// assert_eq!(keys, [1, 1, 2, 3]);
+/// assert_eq!(vals, ["a", "A", "b", "c"]);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `keys` and `vals` differ in length, or if the indices of `keys` cannot be allocated,
+/// which includes every slice of more than `u32::MAX` elements. See [`try_sort_zipped`] for a sort
+/// that returns an error instead of the latter.
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn sort_zipped<K: Ord, V>(keys: &mut [K], vals: &mut [V]) {
+    sort_zipped_by(keys, vals, K::cmp)
+}
+
+/// Sort `keys` with a comparison function `cmp`, moving the elements of `vals` along with them. See
+/// [`sort_zipped`].
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn sort_zipped_by<K, V>(keys: &mut [K], vals: &mut [V], cmp: impl FnMut(&K, &K) -> Ordering) {
+    try_sort_zipped_by(keys, vals, cmp).expect("cannot allocate the indices of the keys")
+}
+
+/// Sort `keys` and move the elements of `vals` along with them as [`sort_zipped`] does, or return
+/// [`SortError::AllocationFailed`], leaving both slices untouched, if the indices of `keys` cannot
+/// be allocated.
+///
+/// ```
+/// let mut keys = [2u32, 1];
+/// let mut vals = ['b', 'a'];
+/// assert_eq!(aerosort::try_sort_zipped(&mut keys, &mut vals), Ok(()));
+/// assert_eq!((keys, vals), ([1, 2], ['a', 'b']));
+/// ```
+///
+/// # Panics
+///
+/// Panics if `keys` and `vals` differ in length.
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn try_sort_zipped<K: Ord, V>(keys: &mut [K], vals: &mut [V]) -> Result<(), SortError> {
+    try_sort_zipped_by(keys, vals, K::cmp)
+}
+
+/// Sort `keys` with a comparison function `cmp`, moving the elements of `vals` along with them, or
+/// return an error if allocation fails. See [`try_sort_zipped`].
+#[cfg(feature = "alloc")]
+pub fn try_sort_zipped_by<K, V>(
+    keys: &mut [K], vals: &mut [V], mut cmp: impl FnMut(&K, &K) -> Ordering,
+) -> Result<(), SortError> {
+    assert_eq!(keys.len(), vals.len(), "keys and values differ in length");
+    metrics::reset();
+
+    let less = &mut |x: &K, y: &K| { metrics::compared(); cmp(x, y) == Ordering::Less };
+    let order = indirect::sorted_order(keys, &SortConfig::DEFAULT, less);
+    let mut order = order.ok_or(SortError::AllocationFailed)?;
+    unsafe { indirect::permute_columns((keys.as_mut_ptr(), vals.as_mut_ptr()), &mut order) }
+    Ok(())
+}
+
+/// Sort `v` with an allocated external buffer as [`sort_alloc`] does, unless allocating it fails,
+/// in which case `v` is sorted in place, like [`sort`], and the error is returned. Either way, `v`
+/// ends up sorted; an error only reports that the buffer was not available. Like [`sort_alloc`],
+/// this sorts the indices of elements of 384 bytes or more, so the buffer is one of indices then.
+///
+/// ```
+/// use std::alloc::{GlobalAlloc, Layout, System};
+/// use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+///
+/// // An allocator that fails every allocation while `FAIL` is set
+/// static FAIL: AtomicBool = AtomicBool::new(false);
+/// struct Flaky;
+/// unsafe impl GlobalAlloc for Flaky {
+///     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+///         if FAIL.load(Relaxed) { std::ptr::null_mut() } else { System.alloc(layout) }
+///     }
+///     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+///         System.dealloc(ptr, layout)
+///     }
+/// }
+///
+/// #[global_allocator]
+/// static ALLOC: Flaky = Flaky;
+///
+/// fn check<T: Clone + Ord>(input: &[T]) {
+///     for fail in [false, true] {
+///         let mut v = input.to_vec();
+///         FAIL.store(fail, Relaxed);
+///         let result = aerosort::sort_alloc_fallible(&mut v);
+///         FAIL.store(false, Relaxed);
+///
+///         assert_eq!(result.is_err(), fail);
+///         assert!(v.windows(2).all(|w| w[0] < w[1]));
+///     }
+/// }
+///
+/// check(&(0..10_000).map(|i| (i as u32 * 7919 % 100, i)).collect::<Vec<_>>());
+/// check(&(0..1000).map(|i| ([i * 7919 % 100; 48], i)).collect::<Vec<_>>());
+/// ```
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn sort_alloc_fallible<T: Ord>(v: &mut [T]) -> Result<(), alloc::collections::TryReserveError> {
+    sort_alloc_fallible_by(v, T::cmp)
+}
+
+/// Sort `v` with an allocated external buffer and a comparison function `cmp`, or in place if
+/// allocating the buffer fails. See [`sort_alloc_fallible`].
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn sort_alloc_fallible_by<T>(
+    v: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering,
+) -> Result<(), alloc::collections::TryReserveError> {
+    // Sort the indices of large elements, as `sort_alloc` does, as long as they fit in `u32`
+    if core::mem::size_of::<T>() >= indirect::MIN_SIZE && u32::try_from(v.len()).is_ok() {
+        metrics::reset();
+        let less = &mut |x: &T, y: &T| { metrics::compared(); cmp(x, y) == Ordering::Less };
+        let config = &SortConfig::DEFAULT;
+        return indirect::try_sorted_order(v, config, less)
+            .map(|mut order| indirect::permute(v, &mut order))
+            .inspect_err(|_| aero::sort_full(v, &mut [], config, less));
+    }
+
+    let len = v.len() / 2;
+    let mut buf = alloc::vec::Vec::<T>::new();
+    let reserved = buf.try_reserve_exact(len);
+
+    match reserved {
+        Ok(()) => sort_with_by(v, Scratch::from_uninit(&mut buf.spare_capacity_mut()[..len]), cmp),
+        Err(_) => sort_by(v, cmp),
+    }
+    reserved
+}
+
+/// Sort `v` with an allocated external buffer of at most `max_bytes` bytes. The buffer holds as
+/// many elements as fit, up to the `v.len() / 2` that every merge can use, so a budget of
+/// `v.len() / 2` elements avoids collecting keys, and a smaller one still speeds up the merges of
+/// shorter runs. A budget too small to hold 8 elements allocates nothing and sorts in place, like
+/// [`sort_by`].
+///
+/// ```
+/// let mut v: Vec<u64> = (0..1000).rev().collect();
+/// aerosort::sort_with_budget(&mut v, 1024);
+/// assert!(v.iter().copied().eq(0..1000));
+/// ```
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn sort_with_budget<T: Ord>(v: &mut [T], max_bytes: usize) {
+    sort_with_budget_by(v, max_bytes, T::cmp)
+}
+
+/// Sort `v` with an allocated external buffer of at most `max_bytes` bytes and a comparison
+/// function `cmp`. See [`sort_with_budget`].
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn sort_with_budget_by<T>(
+    v: &mut [T], max_bytes: usize, cmp: impl FnMut(&T, &T) -> Ordering,
+) {
+    // Zero-sized elements are never sorted, so they need no buffer
+    let fit = max_bytes.checked_div(core::mem::size_of::<T>()).unwrap_or(0);
+
+    // Every run merged has at least 8 elements, so a shorter buffer would never be used
+    let len = match fit.min(v.len() / 2) {
+        len @ 8.. => len,
+        _ => return sort_by(v, cmp),
+    };
+
+    let mut buf = alloc::vec::Vec::<T>::with_capacity(len);
+    sort_with_by(v, Scratch::from_uninit(&mut buf.spare_capacity_mut()[..len]), cmp)
+}
+
+/// Sort every `chunk_len` elements of `v` independently, starting from the front; the last chunk
+/// may be shorter. The chunks can then be merged with [`merge_runs`], with a boundary at every
+/// multiple of `chunk_len`.
+///
+/// ```
+/// let mut v = [5, 1, 4, 2, 9, 3, 8];
+/// aerosort::sort_chunks(&mut v, 3);
+/// assert_eq!(v, [1, 4, 5, 2, 3, 9, 8]);
+///
+/// aerosort::merge_runs(&mut v, &[3, 6]);
+/// assert_eq!(v, [1, 2, 3, 4, 5, 8, 9]);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `chunk_len` is 0.
+#[inline(always)]
+pub fn sort_chunks<T: Ord>(v: &mut [T], chunk_len: usize) {
+    sort_chunks_by(v, chunk_len, T::cmp)
+}
+
+/// Sort every `chunk_len` elements of `v` independently with a comparison function `cmp`. See
+/// [`sort_chunks`].
+#[inline(always)]
+pub fn sort_chunks_by<T>(v: &mut [T], chunk_len: usize, mut cmp: impl FnMut(&T, &T) -> Ordering) {
+    assert!(chunk_len != 0, "chunk length is 0");
+    v.chunks_mut(chunk_len).for_each(|chunk| sort_by(chunk, &mut cmp));
+}
+
+/// Find the natural runs of `v`, the maximal non-descending runs and strictly descending runs that
+/// a natural merge sort (see [`SortConfig::natural_runs`]) starts from, and write the start of each
+/// but the first to `out`. Return the number of such boundaries; if it exceeds `out.len()`, only
+/// the first `out.len()` are written. A slice of `n` elements has at most `n / 2` boundaries.
+///
+/// ```
+/// let v = [3, 2, 1, 1, 2, 5, 4, 0, 7];
+/// let mut out = [0; 4];
+/// assert_eq!(aerosort::detect_runs(&v, &mut out), 3);
+/// assert_eq!(out[..3], [3, 6, 8]);
+/// assert_eq!(aerosort::detect_runs(&[1], &mut out), 0);
+/// ```
+#[inline(always)]
+pub fn detect_runs<T: Ord>(v: &[T], out: &mut [usize]) -> usize {
+    detect_runs_by(v, T::cmp, out)
+}
+
+/// Find the natural runs of `v` with a comparison function `cmp`. See [`detect_runs`].
+#[inline(always)]
+pub fn detect_runs_by<T>(
+    v: &[T], mut cmp: impl FnMut(&T, &T) -> Ordering, out: &mut [usize],
+) -> usize {
+    runs::detect_runs(v, &mut |x, y| cmp(x, y) == Ordering::Less, out)
+}
+
+/// Merge the consecutive sorted runs of `v` delimited by `boundaries`, a non-decreasing list of
+/// indices into `v`. Empty runs are allowed.
+///
+/// # Panics
+///
+/// Panics if `boundaries` is not non-decreasing or contains an index past the end of `v`.
+#[inline(always)]
+pub fn merge_runs<T: Ord>(v: &mut [T], boundaries: &[usize]) {
+    merge_runs_by(v, boundaries, &mut T::cmp)
+}
+
+/// Merge the consecutive sorted runs of `v` delimited by `boundaries` with a comparison function
+/// `cmp`. See [`merge_runs`].
+///
+/// Merging a short run into a long one takes few comparisons when they barely interleave:
+///
+/// ```
+/// let mut v: Vec<u32> = (0..1000).chain(990..1010).collect();
+/// let mut comparisons = 0;
+/// aerosort::merge_runs_by(&mut v, &[1000], |x, y| { comparisons += 1; x.cmp(y) });
+///
+/// assert!(v.windows(2).all(|w| w[0] <= w[1]));
+/// assert!(comparisons <= 64, "{comparisons}");
+/// ```
+#[inline(always)]
+pub fn merge_runs_by<T>(v: &mut [T], boundaries: &[usize], cmp: impl FnMut(&T, &T) -> Ordering) {
+    merge_runs_with_by(v, buffer::create(0), boundaries, cmp)
+}
+
+/// Merge the consecutive sorted runs of `v` delimited by `boundaries` with an external buffer
+/// `ext`. A buffer too short for a merge still speeds up the parts of it that fit. See
+/// [`merge_runs`].
+#[inline(always)]
+pub fn merge_runs_with<T: Ord>(v: &mut [T], ext: impl Buffer<T>, boundaries: &[usize]) {
+    merge_runs_with_by(v, ext, boundaries, &mut T::cmp)
+}
+
+/// Merge the consecutive sorted runs of `v` delimited by `boundaries` with an external buffer
+/// `ext` and a comparison function `cmp`. See [`merge_runs`].
+///
+/// This performs `O(n log k)` comparisons for `k` runs, and `O(n log k)` moves if `ext` can hold
+/// half of `v`.
+#[inline(always)]
+pub fn merge_runs_with_by<T>(
+    v: &mut [T], mut ext: impl Buffer<T>, boundaries: &[usize],
+    mut cmp: impl FnMut(&T, &T) -> Ordering,
 ) {
-    sort_general(v, ext.as_slice_mut(), &mut |x, y| f(x).lt(&f(y)))
+    let ext = scratch::slots(&mut ext);
+    runs::merge_runs(v, ext, boundaries, &mut |x, y| cmp(x, y) == Ordering::Less)
+}
+
+/// Merge the consecutive runs of `v` delimited by `boundaries`, where run `i` is sorted in
+/// descending order iff `descending[i]`, and in ascending order otherwise. Descending runs are
+/// reversed first, keeping equal elements in their original order, so the result is the same as a
+/// stable sort of `v` would give. See [`merge_runs`].
+///
+/// ```
+/// let mut v = [(3, 'a'), (1, 'b'), (1, 'c'), (2, 'd'), (4, 'e'), (2, 'f'), (0, 'g')];
+/// aerosort::merge_runs_directed_by(&mut v, &[3, 5], &[true, false, true], |x, y| x.0.cmp(&y.0));
+/// assert_eq!(v, [(0, 'g'), (1, 'b'), (1, 'c'), (2, 'd'), (2, 'f'), (3, 'a'), (4, 'e')]);
+///
+/// let mut v = [5, 4, 4, 1];
+/// aerosort::merge_runs_directed(&mut v, &[], &[true]);
+/// assert_eq!(v, [1, 4, 4, 5]);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `boundaries` is not non-decreasing or contains an index past the end of `v`, or if
+/// `descending.len() != boundaries.len() + 1`.
+#[inline(always)]
+pub fn merge_runs_directed<T: Ord>(v: &mut [T], boundaries: &[usize], descending: &[bool]) {
+    merge_runs_directed_by(v, boundaries, descending, T::cmp)
+}
+
+/// Merge the consecutive runs of `v` delimited by `boundaries` and sorted in the directions given
+/// by `descending` with a comparison function `cmp`. See [`merge_runs_directed`].
+#[inline(always)]
+pub fn merge_runs_directed_by<T>(
+    v: &mut [T], boundaries: &[usize], descending: &[bool],
+    mut cmp: impl FnMut(&T, &T) -> Ordering,
+) {
+    let less = &mut |x: &T, y: &T| cmp(x, y) == Ordering::Less;
+    runs::merge_directed_runs(v, &mut [], boundaries, descending, less)
+}
+
+/// Merge the sorted slices `a` and `b` into a new sorted vector of clones with a comparison
+/// function `cmp`, leaving both untouched. Equal elements from `a` come before those from `b`.
+///
+/// ```
+/// let merged = aerosort::merged(&[1, 3, 5], &[2, 3, 4], i32::cmp);
+/// assert_eq!(merged, [1, 2, 3, 3, 4, 5]);
+/// ```
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn merged<T: Clone>(
+    a: &[T], b: &[T], mut cmp: impl FnMut(&T, &T) -> Ordering,
+) -> alloc::vec::Vec<T> {
+    merge::merge_cloned(a, b, &mut |x, y| cmp(x, y) == Ordering::Less)
+}
+
+/// Return the index of the first element of the sorted slice `v` that is not less than `x`, or
+/// `v.len()` if there is none. This compares elements exactly as sorting does, so it agrees with
+/// the order a sort produced.
+///
+/// Cost: `O(log n)` comparisons.
+#[inline(always)]
+pub fn lower_bound<T: Ord>(v: &[T], x: &T) -> usize {
+    lower_bound_by(v, x, T::cmp)
+}
+
+/// Return the index of the first element of the sorted slice `v` that is not less than `x` with a
+/// comparison function `cmp`. See [`lower_bound`].
+#[inline(always)]
+pub fn lower_bound_by<T>(v: &[T], x: &T, mut cmp: impl FnMut(&T, &T) -> Ordering) -> usize {
+    sort_util::op::lower_bound::binary(v.len(), |i| cmp(&v[i], x) == Ordering::Less)
+}
+
+/// Return the index of the first element of the sorted slice `v` that is greater than `x`, or
+/// `v.len()` if there is none. See [`lower_bound`].
+///
+/// Cost: `O(log n)` comparisons.
+#[inline(always)]
+pub fn upper_bound<T: Ord>(v: &[T], x: &T) -> usize {
+    upper_bound_by(v, x, T::cmp)
+}
+
+/// Return the index of the first element of the sorted slice `v` that is greater than `x` with a
+/// comparison function `cmp`. See [`upper_bound`].
+#[inline(always)]
+pub fn upper_bound_by<T>(v: &[T], x: &T, mut cmp: impl FnMut(&T, &T) -> Ordering) -> usize {
+    sort_util::op::lower_bound::binary(v.len(), |i| cmp(x, &v[i]) != Ordering::Less)
+}
+
+/// Return the range of elements of the sorted slice `v` that are equal to `x`, which is empty (and
+/// starts where `x` would be inserted) if there are none.
+///
+/// ```
+/// let v = [1, 2, 2, 2, 3, 5];
+/// assert_eq!(aerosort::equal_range(&v, &2), 1..4);
+/// assert_eq!(aerosort::equal_range(&v, &4), 5..5);
+/// ```
+///
+/// Cost: `O(log n)` comparisons.
+#[inline(always)]
+pub fn equal_range<T: Ord>(v: &[T], x: &T) -> core::ops::Range<usize> {
+    equal_range_by(v, x, T::cmp)
+}
+
+/// Return the range of elements of the sorted slice `v` that are equal to `x` with a comparison
+/// function `cmp`. See [`equal_range`].
+#[inline(always)]
+pub fn equal_range_by<T>(
+    v: &[T], x: &T, mut cmp: impl FnMut(&T, &T) -> Ordering,
+) -> core::ops::Range<usize> {
+    let start = lower_bound_by(v, x, &mut cmp);
+    start..start + upper_bound_by(&v[start..], x, cmp)
+}
+
+/// Move the last element of `v` into place among the sorted elements before it, after every
+/// element equal to it, and return its new index. Pushing each new element onto a sorted slice and
+/// calling this keeps the slice sorted, with equal elements in the order they were added.
+///
+/// ```
+/// let mut v = [(1, 'a'), (2, 'a'), (2, 'b'), (4, 'a'), (2, 'c')];
+/// assert_eq!(aerosort::insert_last_by_key(&mut v, |x| x.0), 3);
+/// assert_eq!(v, [(1, 'a'), (2, 'a'), (2, 'b'), (2, 'c'), (4, 'a')]);
+///
+/// let mut v = [2, 3, 1];
+/// assert_eq!(aerosort::insert_last(&mut v), 0);
+/// assert_eq!(v, [1, 2, 3]);
+///
+/// let mut v = [1, 2, 3];
+/// assert_eq!(aerosort::insert_last(&mut v), 2);
+/// assert_eq!(v, [1, 2, 3]);
+/// ```
+///
+/// Cost: `O(log n)` comparisons and `O(n)` moves.
+///
+/// # Panics
+///
+/// Panics if `v` is empty.
+#[inline(always)]
+pub fn insert_last<T: Ord>(v: &mut [T]) -> usize {
+    insert_last_by(v, T::cmp)
+}
+
+/// Move the last element of `v` into place among the sorted elements before it with a comparison
+/// function `cmp`, and return its new index. See [`insert_last`].
+#[inline(always)]
+pub fn insert_last_by<T>(v: &mut [T], cmp: impl FnMut(&T, &T) -> Ordering) -> usize {
+    let (x, sorted) = v.split_last().expect("cannot insert the last element of an empty slice");
+    let [index, n] = [upper_bound_by(sorted, x, cmp), v.len()];
+
+    unsafe { sort_util::op::insert_left(v.as_mut_ptr().add(n - 1), n - 1 - index) }
+    metrics::moved(n - index);
+    index
+}
+
+/// Move the last element of `v` into place among the sorted elements before it with a mapping `f`
+/// from elements to keys, and return its new index. See [`insert_last`].
+#[inline(always)]
+pub fn insert_last_by_key<T, K: Ord>(v: &mut [T], mut f: impl FnMut(&T) -> K) -> usize {
+    insert_last_by(v, |x, y| f(x).cmp(&f(y)))
+}
+
+/// Insert `x` into the sorted vector `v` after every element equal to it, and return its index.
+///
+/// ```
+/// let mut v = vec![1, 3];
+/// for x in [2, 0, 4] {
+///     aerosort::insert_sorted(&mut v, x);
+/// }
+/// assert_eq!(v, [0, 1, 2, 3, 4]);
+/// ```
+///
+/// Cost: `O(log n)` comparisons and `O(n)` moves.
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn insert_sorted<T: Ord>(v: &mut alloc::vec::Vec<T>, x: T) -> usize {
+    insert_sorted_by(v, x, T::cmp)
+}
+
+/// Insert `x` into the sorted vector `v` with a comparison function `cmp`, and return its index.
+/// See [`insert_sorted`].
+#[cfg(feature = "alloc")]
+#[inline(always)]
+pub fn insert_sorted_by<T>(
+    v: &mut alloc::vec::Vec<T>, x: T, cmp: impl FnMut(&T, &T) -> Ordering,
+) -> usize {
+    v.push(x);
+    insert_last_by(v, cmp)
+}
+
+/// Call `f` on every element of the sorted slices `a` and `b` in merged order with a comparison
+/// function `cmp`, without copying or moving either. Equal elements from `a` are passed to `f`
+/// before those from `b`, so this can drive a merge-join over two sorted inputs.
+///
+/// ```
+/// let mut out = Vec::new();
+/// let (a, b) = ([(1, 'a'), (3, 'a')], [(1, 'b'), (2, 'b')]);
+/// aerosort::merge_streaming(&a, &b, |x, y| x.0.cmp(&y.0), |x| out.push(*x));
+/// assert_eq!(out, [(1, 'a'), (1, 'b'), (2, 'b'), (3, 'a')]);
+/// ```
+#[inline(always)]
+pub fn merge_streaming<T>(
+    a: &[T], b: &[T], mut cmp: impl FnMut(&T, &T) -> Ordering, mut f: impl FnMut(&T),
+) {
+    merge::merge_each(a, b, &mut |x, y| cmp(x, y) == Ordering::Less, &mut f)
+}
+
+/// Merge the sorted slices `a` and `b` into `dst` with a comparison function `cmp`, cloning each
+/// element into place. Equal elements from `a` come before those from `b`.
+///
+/// ```
+/// let mut dst = [0; 5];
+/// aerosort::merge_into(&[1, 4, 5], &[2, 3], &mut dst, i32::cmp);
+/// assert_eq!(dst, [1, 2, 3, 4, 5]);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `dst.len() != a.len() + b.len()`:
+///
+/// ```should_panic
+/// aerosort::merge_into(&[1, 4, 5], &[2, 3], &mut [0; 4], i32::cmp);
+/// ```
+#[inline(always)]
+pub fn merge_into<T: Clone>(
+    a: &[T], b: &[T], dst: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering,
+) {
+    assert_eq!(a.len() + b.len(), dst.len(), "destination length differs from the merged length");
+
+    let mut slots = dst.iter_mut();
+    merge::merge_each(a, b, &mut |x, y| cmp(x, y) == Ordering::Less, &mut |x| {
+        slots.next().unwrap().clone_from(x)
+    })
+}
+
+/// Merge the sorted slices `a` and `b` into `dst` with a comparison function `cmp` as
+/// [`merge_into`] does, stopping once `dst` is full. Return `[ra, rb]`, the lengths of the tails of
+/// `a` and `b` that were not merged: `dst` starts with the merge of `a[..a.len() - ra]` and
+/// `b[..b.len() - rb]`, and every element of the tails belongs after it.
+///
+/// Exactly `min(dst.len(), a.len() + b.len())` elements are written, so `ra + rb` is the number of
+/// elements that did not fit. Merging the tails next continues the same merge, equal elements from
+/// `a` still coming first:
+///
+/// ```
+/// let (a, b) = ([1, 3, 5, 7], [2, 3, 4]);
+/// let mut dst = [0; 4];
+///
+/// let [ra, rb] = aerosort::merge_into_bounded(&a, &b, &mut dst, i32::cmp);
+/// assert_eq!(dst, [1, 2, 3, 3]);
+/// assert_eq!(ra + rb, a.len() + b.len() - dst.len());
+/// let (a, b) = (&a[a.len() - ra..], &b[b.len() - rb..]);
+/// assert_eq!((a, b), (&[5, 7][..], &[4][..]));
+///
+/// let [ra, rb] = aerosort::merge_into_bounded(a, b, &mut dst, i32::cmp);
+/// assert_eq!(([ra, rb], &dst[..3]), ([0, 0], &[4, 5, 7][..]));
+/// ```
+#[inline(always)]
+pub fn merge_into_bounded<T: Clone>(
+    a: &[T], b: &[T], dst: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering,
+) -> [usize; 2] {
+    let mut slots = dst.iter_mut();
+    let less = &mut |x: &T, y: &T| cmp(x, y) == Ordering::Less;
+    merge::merge_each_bounded(a, b, slots.len(), less, &mut |x| {
+        slots.next().unwrap().clone_from(x)
+    })
+}
+
+/// Merge the sorted runs `v[..mid]` and `v[mid..]` with a comparison function `cmp`, using `buf`
+/// as a buffer of arbitrary elements. The shorter run is swapped into `buf` and merged back with
+/// swaps, so `buf` ends up holding its original elements, in an unspecified order, and no element
+/// is ever copied or dropped. This is the merge a sort performs with its internal buffer of keys.
+///
+/// ```
+/// let mut v = [1, 4, 6, 2, 3, 5, 7];
+/// let mut buf = [0, -1, -2];
+/// aerosort::merge_swap(&mut v, 3, &mut buf, i32::cmp);
+/// assert_eq!(v, [1, 2, 3, 4, 5, 6, 7]);
+/// buf.sort();
+/// assert_eq!(buf, [-2, -1, 0]);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `mid > v.len()`, or if `buf` is shorter than the shorter run.
+#[inline(always)]
+pub fn merge_swap<T>(
+    v: &mut [T], mid: usize, buf: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering,
+) {
+    let (a, b) = v.split_at_mut(mid);
+    assert!(buf.len() >= a.len().min(b.len()), "buffer is shorter than the shorter run");
+    let less = &mut |x: &T, y: &T| cmp(x, y) == Ordering::Less;
+    unsafe { merge::merge_swapping([a, b], buf.as_mut_ptr(), less) }
+}
+
+/// Sort `v`, a slice of primitive values, by their natural order. Floating-point values are sorted
+/// by total order (see [`sort_floats`]). Slices of `u8` or `bool` are sorted in linear time by
+/// counting their values.
+///
+/// With the `network` feature, other slices of at most 32 elements are sorted with a branchless
+/// sorting network, as are the short pieces that longer slices are merged from. With the
+/// `x86-simd` feature, longer slices of `i32`, `u32`, or `f32` are merged 8 elements at a time with
+/// AVX2 instructions on CPUs that support them, using a buffer of `n / 2` elements.
+///
+/// ```
+/// let mut v = *b"aerosort";
+/// aerosort::sort_primitive(&mut v);
+/// assert_eq!(&v, b"aeoorrst");
+///
+/// let mut flags = [true, false, true, false];
+/// aerosort::sort_primitive(&mut flags);
+/// assert_eq!(flags, [false, false, true, true]);
+/// # #[cfg(feature = "metrics")]
+/// # assert_eq!(aerosort::last_sort_stats().comparisons, 0);
+/// ```
+#[inline(always)]
+pub fn sort_primitive<T: Primitive>(v: &mut [T]) {
+    if T::BYTE {
+        metrics::reset();
+        return mini::byte_counting_sort(v);
+    }
+
+    #[cfg(feature = "network")]
+    if v.len() <= 32 {
+        return mini::network_sort(v);
+    }
+
+    #[cfg(feature = "x86-simd")]
+    if let Some(lane) = T::LANE {
+        if simd::sort_lanes(v, lane) {
+            return;
+        }
+    }
+
+    // Sort the short pieces that we merge with the network too
+    #[cfg(feature = "network")]
+    {
+        metrics::reset();
+        let less = &mut |x: &T, y: &T| { metrics::compared(); x.lt(*y) };
+        let (config, base) = (&SortConfig::DEFAULT, &mut mini::NetworkSort);
+        aero::sort_cancellable(v, &mut [], config, base, less, &mut || false);
+    }
+
+    #[cfg(not(feature = "network"))]
+    sort_general(v, &mut [], &mut |x, y| x.lt(*y));
+}
+
+/// Sort `arr`, an array of primitive values, by their natural order. Arrays of at most 32 elements
+/// are sorted with a sorting network specialized for `N`, which the compiler can fully unroll;
+/// larger arrays are sorted with [`sort_primitive`].
+///
+/// ```
+/// let mut arr = [5u32, 3, 8, 1];
+/// aerosort::sort_array(&mut arr);
+/// assert_eq!(arr, [1, 3, 5, 8]);
+/// ```
+#[cfg(feature = "network")]
+#[inline(always)]
+pub fn sort_array<T: Primitive, const N: usize>(arr: &mut [T; N]) {
+    if N <= 32 {
+        mini::network_sort_array(arr)
+    } else {
+        sort_primitive(arr)
+    }
+}
+
+/// Sort `v` with a radix sort, using an external buffer `ext`. If `ext` cannot hold all of `v`,
+/// this falls back to [`sort_with`].
+///
+/// This performs `O(n)` moves, skipping passes over digits that every element shares, so it beats
+/// comparison sorts on large slices.
+///
+/// ```
+/// let mut v = [300, 2, 1 << 40, 7];
+/// let mut buf = [0; 4];
+/// aerosort::sort_radix_u64(&mut v, &mut buf[..]);
+/// assert_eq!(v, [2, 7, 300, 1 << 40]);
+/// ```
+#[cfg(feature = "radix")]
+#[inline(always)]
+pub fn sort_radix_u64(v: &mut [u64], mut ext: impl Buffer<u64>) {
+    let ext = scratch::slots(&mut ext);
+    if ext.len() >= v.len() {
+        radix::radix_sort(v, ext, |x| x)
+    } else {
+        sort_with(v, ext)
+    }
+}
+
+/// Sort `v` with a radix sort, using an external buffer `ext`. See [`sort_radix_u64`].
+#[cfg(feature = "radix")]
+#[inline(always)]
+pub fn sort_radix_i64(v: &mut [i64], mut ext: impl Buffer<i64>) {
+    let ext = scratch::slots(&mut ext);
+    if ext.len() >= v.len() {
+        radix::radix_sort(v, ext, radix::i64_key)
+    } else {
+        sort_with(v, ext)
+    }
+}
+
+/// Sort `v` with a mapping `f` from elements to 32-bit keys with an in-place radix sort, letting
+/// elements with equal keys end up in any order. This needs no external buffer and never compares
+/// elements, so it suits large slices keyed by hashes or identifiers.
+///
+/// Each of at most 4 passes swaps elements into the buckets of one byte of their keys, skipping
+/// bytes that every key in a bucket shares, and calls `f` twice per element. Buckets of at most 32
+/// elements are finished with insertion sort by key. The `u32_key` example compares this with
+/// [`sort_by_key`].
+///
+/// ```
+/// let hash = |i: u32| i.wrapping_mul(0x9e37_79b9);
+/// for mask in [u32::MAX, 0xff00, 7] {
+///     let mut v: Vec<(u32, u32)> = (0..10_000).map(|i| (hash(i) & mask, i)).collect();
+///     aerosort::sort_unstable_by_u32_key(&mut v, |x| x.0);
+///     assert!(v.windows(2).all(|w| w[0].0 <= w[1].0));
+///
+///     // Every element is still there
+///     let mut ids: Vec<u32> = v.iter().map(|x| x.1).collect();
+///     ids.sort();
+///     assert!(ids.into_iter().eq(0..10_000));
+/// }
+/// ```
+///
+/// Cost: `O(n)` calls to `f` and `O(n)` swaps.
+#[cfg(feature = "radix")]
+#[inline(always)]
+pub fn sort_unstable_by_u32_key<T>(v: &mut [T], mut f: impl FnMut(&T) -> u32) {
+    metrics::reset();
+    radix::flag_sort(v, 24, &mut f)
+}
+
+/// Sort `v` with an external buffer `ext` and a mapping `f` from elements to 32-bit keys, letting
+/// elements with equal keys end up in any order. If `ext` can hold all of `v`, this distributes the
+/// elements through it with a least significant digit radix sort, which makes at most 3 passes and
+/// calls `f` once per element per pass; otherwise it sorts in place as
+/// [`sort_unstable_by_u32_key`] does.
+///
+/// ```
+/// let mut v = [(7, 'a'), (1 << 20, 'b'), (7, 'c'), (0, 'd')];
+/// let mut buf = [(0, ' '); 4];
+/// aerosort::sort_unstable_with_by_u32_key(&mut v, &mut buf[..], |x| x.0);
+/// assert!(v.windows(2).all(|w| w[0].0 <= w[1].0));
+/// ```
+#[cfg(feature = "radix")]
+#[inline(always)]
+pub fn sort_unstable_with_by_u32_key<T: Copy>(
+    v: &mut [T], mut ext: impl Buffer<T>, f: impl Fn(&T) -> u32,
+) {
+    let ext = scratch::slots(&mut ext);
+    if ext.len() >= v.len() {
+        metrics::reset();
+        radix::radix_sort(v, ext, |x| f(&x) as u64)
+    } else {
+        sort_unstable_by_u32_key(v, f)
+    }
+}
+
+/// Stably sort `v` with an external buffer `ext` and a mapping `f` from elements to byte string
+/// keys, ordered lexicographically as `&[u8]` is, with a most significant byte first radix sort. If
+/// `ext` cannot hold all of `v`, this falls back to [`sort_with_by_key`].
+///
+/// Each pass splits the elements by one byte of their keys, skipping bytes that every key in a
+/// bucket shares. Buckets of at most 32 elements, and elements whose keys have ended, are finished
+/// by comparing keys, so this suits long slices keyed by strings or encoded records, where
+/// comparing whole keys over and over would be costly: a prefix shared by a bucket is skipped once,
+/// rather than read again by each of the `O(n log n)` comparisons of [`sort_with`].
+///
+/// ```
+/// use aerosort::Scratch;
+/// use core::mem::MaybeUninit;
+///
+/// // A key sorts before every key it is a prefix of, and equal keys keep their order
+/// let mut v = [("ab", 0), ("b", 1), ("", 2), ("abc", 3), ("a", 4), ("ab", 5), ("ba", 6)];
+/// aerosort::sort_with_by_bytes(&mut v, &mut [("", 0); 7][..], |x| x.0.as_bytes());
+/// assert_eq!(v.map(|x| x.1), [2, 4, 0, 5, 3, 1, 6]);
+///
+/// // Longer slices split into buckets by byte until they are short enough to compare
+/// let mut v: Vec<_> = (0..5000).map(|i| ((i * 7919 % 613).to_string(), i)).collect();
+/// let mut expected = v.clone();
+/// expected.sort_by(|x, y| x.0.cmp(&y.0));
+///
+/// let mut buf = [const { MaybeUninit::uninit() }; 5000];
+/// aerosort::sort_with_by_bytes(&mut v, Scratch::from_uninit(&mut buf), |x| x.0.as_bytes());
+/// assert_eq!(v, expected);
+/// ```
+///
+/// Only `Copy` elements can be scattered into an initialized buffer (see [`Buffer`]). Others go
+/// through uninitialized memory, and each is still dropped exactly once, by its owner:
+///
+/// ```
+/// use core::cell::Cell;
+/// use core::mem::MaybeUninit;
+///
+/// struct Tracked<'a>(String, &'a Cell<usize>);
+///
+/// impl Drop for Tracked<'_> {
+///     fn drop(&mut self) {
+///         self.1.set(self.1.get() + 1);
+///     }
+/// }
+///
+/// let drops = Cell::new(0);
+/// let key = |i: usize| (i * 7919 % 613).to_string();
+/// let mut v: Vec<_> = (0..1000).map(|i| Tracked(key(i), &drops)).collect();
+/// let mut buf: Vec<MaybeUninit<Tracked>> = (0..1000).map(|_| MaybeUninit::uninit()).collect();
+/// aerosort::sort_with_by_bytes(&mut v, &mut buf[..], |x| x.0.as_bytes());
+/// assert!(v.windows(2).all(|w| w[0].0 <= w[1].0));
+///
+/// drop((v, buf));
+/// assert_eq!(drops.get(), 1000);
+/// ```
+#[cfg(feature = "radix")]
+#[inline(always)]
+pub fn sort_with_by_bytes<T>(
+    v: &mut [T], mut ext: impl Buffer<T>, mut f: impl FnMut(&T) -> &[u8],
+) {
+    let ext = scratch::slots(&mut ext);
+    if ext.len() >= v.len() {
+        radix::byte_sort(v, ext, 0, &mut f)
+    } else {
+        sort_general(v, ext, &mut |x, y| f(x) < f(y))
+    }
+}
+
+/// Sort `v` by total order (see [`sort_floats`]) with a radix sort, using an external buffer
+/// `ext`. See [`sort_radix_u64`].
+#[cfg(feature = "radix")]
+#[inline(always)]
+pub fn sort_radix_f64(v: &mut [f64], mut ext: impl Buffer<f64>) {
+    let ext = scratch::slots(&mut ext);
+    if ext.len() >= v.len() {
+        radix::radix_sort(v, ext, radix::f64_key)
+    } else {
+        sort_with_by(v, ext, f64::total_cmp)
+    }
+}
+
+/// Collect keys from `v` with a comparison function `cmp` as a sort would, and report how many
+/// distinct values were found and what external buffer would help. See [`state::analyze`].
+///
+/// ```
+/// let mut v = [7; 100];
+/// let analysis = aerosort::analyze(&mut v, i32::cmp);
+/// assert_eq!(analysis.distinct_lower_bound, 1);
+/// ```
+#[cfg(feature = "internal")]
+#[inline(always)]
+pub fn analyze<T>(
+    v: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering,
+) -> state::SortAnalysis {
+    state::analyze(v, &mut |x, y| cmp(x, y) == Ordering::Less)
+}
+
+/// Sort `v` using binary insertion sort. This performs only `O(n log n)` comparisons but `O(n^2)`
+/// moves, so it suits short slices with expensive comparisons. To sort the short pieces of a longer
+/// sort this way, pass [`BinaryInsertionSort`] to [`sort_with_base`].
+///
+/// ```
+/// let reversed: Vec<u32> = (0..100).rev().collect();
+/// let mut v = reversed.clone();
+/// aerosort::binary_insertion_sort(&mut v);
+/// assert!(v.iter().copied().eq(0..100));
+///
+/// // Insertion sort compares each element with every one before it, while binary insertion sort
+/// // takes at most `ceil(log2(i + 1))` comparisons to insert the element at `i`
+/// let [mut linear, mut binary] = [0, 0];
+/// aerosort::insertion_sort_by(&mut reversed.clone(), |x, y| { linear += 1; x.cmp(y) });
+/// aerosort::binary_insertion_sort_by(&mut reversed.clone(), |x, y| { binary += 1; x.cmp(y) });
+/// assert_eq!(linear, 100 * 99 / 2);
+/// assert!(binary <= (1..100).map(|i: u32| (i + 1).next_power_of_two().ilog2()).sum::<u32>());
+/// ```
+#[inline(always)]
+pub fn binary_insertion_sort<T: Ord>(v: &mut [T]) {
+    binary_insertion_sort_by(v, T::cmp)
+}
+
+/// Sort `v` with a comparison function `cmp` using binary insertion sort. See
+/// [`binary_insertion_sort`].
+#[inline(always)]
+pub fn binary_insertion_sort_by<T>(v: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering) {
+    mini::binary_insertion_sort(v, &mut |x, y| cmp(x, y) == Ordering::Less)
+}
+
+/// Sort `v` using insertion sort. This performs `O(n^2)` comparisons and moves, but no setup, so it
+/// suits very short slices.
+///
+/// ```
+/// let mut v = [3, 1, 2];
+/// aerosort::insertion_sort(&mut v);
+/// assert_eq!(v, [1, 2, 3]);
+/// ```
+#[inline(always)]
+pub fn insertion_sort<T: Ord>(v: &mut [T]) {
+    insertion_sort_by(v, T::cmp)
+}
+
+/// Sort `v` with a comparison function `cmp` using insertion sort. See [`insertion_sort`].
+#[inline(always)]
+pub fn insertion_sort_by<T>(v: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering) {
+    mini::insertion_sort_safe(v, &mut |x, y| cmp(x, y) == Ordering::Less)
+}
+
+/// Sort `v`, letting equal elements end up in any order. Slices of at most 8 elements are sorted
+/// with a sorting network of the fewest comparators known for their length, whose conditional swaps
+/// are branchless; longer slices are sorted like [`sort`].
+///
+/// ```
+/// // Every permutation of every length up to 8, numbered in the factorial number system
+/// for n in 0..=8 {
+///     for mut k in 0..(1..=n).product() {
+///         let mut pool: Vec<usize> = (0..n).collect();
+///         let mut v: Vec<usize> = (1..=n).rev().map(|m| (pool.remove(k % m), k /= m).0).collect();
+///         aerosort::sort_unstable(&mut v);
+///         assert!(v.into_iter().eq(0..n));
+///     }
+/// }
+/// ```
+#[inline(always)]
+pub fn sort_unstable<T: Ord>(v: &mut [T]) {
+    sort_unstable_by(v, T::cmp)
+}
+
+/// Sort `v` with a comparison function `cmp`, letting equal elements end up in any order. See
+/// [`sort_unstable`].
+#[inline(always)]
+pub fn sort_unstable_by<T>(v: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering) {
+    let less = &mut |x: &T, y: &T| cmp(x, y) == Ordering::Less;
+    sort_unstable_configured(v, &mut [], &SortConfig::DEFAULT, less)
+}
+
+/// Sort `v` with a mapping `f` from elements to keys, letting elements with equal keys end up in
+/// any order. See [`sort_unstable`].
+#[inline(always)]
+pub fn sort_unstable_by_key<T, K: Ord>(v: &mut [T], mut f: impl FnMut(&T) -> K) {
+    sort_unstable_by(v, |x, y| f(x).cmp(&f(y)))
+}
+
+/// Sort `v`, expecting it to be nearly sorted, such as a log with a few entries out of place. This
+/// skips collecting keys: it inserts every element that is out of order into the sorted prefix
+/// before it, galloping back from the end of the prefix, and gives up once the elements shifted
+/// add up to `4n`, sorting the rest as [`sort`] does and merging it in.
+///
+/// When `k` elements are each out of place by at most `d` positions, with `k d <= 4n`, this takes
+/// `O(n + k log d)` comparisons and `O(n + k d)` moves, where [`sort`] takes `O(n log n)`. Elements
+/// moved anywhere in the slice soon exhaust the budget, and this costs about as much as [`sort`].
+/// On random input, it takes the same `O(n log n)` comparisons and moves as [`sort`].
+///
+/// ```
+/// // A log of timestamps in which every hundredth entry arrived up to 50 entries late
+/// let mut v: Vec<u32> = (0..10_000).collect();
+/// (0..100).for_each(|i| v[i * 100..i * 100 + 51].rotate_left(1));
+/// let mut comparisons = 0;
+/// aerosort::sort_hint_nearly_sorted_by(&mut v, |x, y| { comparisons += 1; x.cmp(y) });
+///
+/// assert!(v.iter().copied().eq(0..10_000));
+/// assert!(comparisons < 11_200);    // `n - 1`, plus a gallop for every late entry
+/// ```
+#[inline(always)]
+pub fn sort_hint_nearly_sorted<T: Ord>(v: &mut [T]) {
+    sort_hint_nearly_sorted_by(v, T::cmp)
+}
+
+/// Sort `v` with a comparison function `cmp`, expecting it to be nearly sorted. See
+/// [`sort_hint_nearly_sorted`].
+#[inline(always)]
+pub fn sort_hint_nearly_sorted_by<T>(v: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering) {
+    metrics::reset();
+    let less = &mut |x: &T, y: &T| { metrics::compared(); cmp(x, y) == Ordering::Less };
+    runs::sort_nearly_sorted(v, v.len().saturating_mul(4), less)
+}
+
+/// Sort `v` with a mapping `f` from elements to keys, expecting it to be nearly sorted. See
+/// [`sort_hint_nearly_sorted`].
+#[inline(always)]
+pub fn sort_hint_nearly_sorted_by_key<T, K: Ord>(v: &mut [T], mut f: impl FnMut(&T) -> K) {
+    sort_hint_nearly_sorted_by(v, |x, y| f(x).cmp(&f(y)))
+}
+
+/// Sort `v` using heap sort. This performs `O(n log n)` comparisons and moves without any extra
+/// space or recursion, but unlike the other sorts in this crate, it is not stable.
+///
+/// Giving up stability buys no speed on input with few distinct values: given an external buffer
+/// of `n / 32` elements, [`sort_with`] distributes such a slice among its distinct values directly,
+/// and is faster than this with up to 12 of them.
+///
+/// ```
+/// let mut v = [5, 3, 8, 1, 9, 2];
+/// aerosort::heap_sort(&mut v);
+/// assert_eq!(v, [1, 2, 3, 5, 8, 9]);
+///
+/// // Slices of fewer than 2 elements are left as they are, without a comparison
+/// let mut comparisons = 0;
+/// for v in [&mut [][..], &mut [7][..]] {
+///     aerosort::heap_sort_by(v, |x: &i32, y| { comparisons += 1; x.cmp(y) });
+/// }
+/// assert_eq!(comparisons, 0);
+/// ```
+#[inline(always)]
+pub fn heap_sort<T: Ord>(v: &mut [T]) {
+    heap_sort_by(v, T::cmp)
+}
+
+/// Sort `v` with a comparison function `cmp` using heap sort. See [`heap_sort`].
+#[inline(always)]
+pub fn heap_sort_by<T>(v: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering) {
+    mini::heap_sort(v, &mut |x, y| cmp(x, y) == Ordering::Less)
 }
 
 #[inline(always)]
 fn sort_general<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], ext: &mut [T], less: &mut F) {
+    sort_configured(v, ext, &SortConfig::DEFAULT, less)
+}
+
+#[inline(always)]
+fn sort_unstable_configured<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], ext: &mut [T], config: &SortConfig, less: &mut F,
+) {
+    if v.len() <= 8 {
+        metrics::reset();
+        mini::network_sort_small(v, &mut |x, y| { metrics::compared(); less(x, y) });
+    } else {
+        sort_configured(v, ext, config, less);
+    }
+}
+
+#[inline(always)]
+fn sort_configured<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], ext: &mut [T], config: &SortConfig, less: &mut F,
+) {
+    metrics::reset();
+
     // Skip zero-sized types
     if core::mem::size_of::<T>() != 0 {
-        aero::sort_full(v, ext, less);
+        aero::sort_full(v, ext, config, &mut |x, y| { metrics::compared(); less(x, y) });
     }
 }
@@ -1,21 +1,39 @@
-use sort_util::op::{move_slice, rotate, search, write};
-use sort_util::{RawMut, Sorted};
+use sort_util::op::{lower_bound, move_slice, rotate, search, write};
+use sort_util::{GenerateSlice, RawMut, Sorted};
+
+use crate::metrics;
 
 /// A trait for merging two sorted slices under the assumption that the operation is possible.
 pub trait MergeUnchecked<T> {
-    fn merge_unchecked<F: FnMut(&T, &T) -> bool>(&mut self, pair: [&mut [T]; 2], less: &mut F);
+    /// Merge the sorted runs `a` and `b` by `less`.
+    ///
+    /// # Safety
+    ///
+    /// `b` directly follows `a` in memory, neither overlaps `self`, and
+    /// [`can_merge`](Merge::can_merge) holds for them.
+    unsafe fn merge_unchecked<F: FnMut(&T, &T) -> bool>(
+        &mut self, pair: [&mut [T]; 2], less: &mut F,
+    );
 }
 
-/// A trait for merging two sorted slices with a safety check.
+/// A trait for merging two sorted slices with a safety check. It is implemented for slices, used
+/// as external buffers, and for [`Keys`](crate::keys::Keys).
 pub trait Merge<T>: MergeUnchecked<T> {
+    /// Return `true` iff `self` can merge `a` and `b`.
     fn can_merge(&self, pair: [&mut [T]; 2]) -> bool;
 
     /// Try to merge `a` and `b` and return [`Sorted::Done`]. Otherwise, return [`Sorted::Fail`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `b` does not directly follow `a` in memory.
     fn merge<F: FnMut(&T, &T) -> bool>(&mut self, [a, b]: [&mut [T]; 2], less: &mut F) -> Sorted {
+        assert!(a.as_ptr_range().end == b.as_ptr(), "runs are not adjacent");
         if !self.can_merge([a, b]) {
             Sorted::Fail
         } else {
-            self.merge_unchecked([a, b], less);
+            // `self` is borrowed apart from both runs, so it cannot overlap them
+            unsafe { self.merge_unchecked([a, b], less); }
             Sorted::Done
         }
     }
@@ -32,7 +50,10 @@ impl<T> MergeUnchecked<T> for [T] {
     /// Copy either `a` or `b` into `self` and merge.
     ///
     /// Cost: `O(n + m)` comparisons and `O(n + m)` moves.
-    fn merge_unchecked<F: FnMut(&T, &T) -> bool>(&mut self, [a, b]: [&mut [T]; 2], less: &mut F) {
+    unsafe fn merge_unchecked<F: FnMut(&T, &T) -> bool>(
+        &mut self, [a, b]: [&mut [T]; 2], less: &mut F,
+    ) {
+        metrics::moved(a.len().min(b.len()));
         unsafe {
             if a.len() <= b.len() {
                 merge_up::<_, false>([move_slice::<_, false>(self.as_mut_ptr(), a), b], less);
@@ -45,6 +66,9 @@ impl<T> MergeUnchecked<T> for [T] {
 
 /// Merge `a` and `b` starting at `dst` and building the result rightwards.
 ///
+/// The inner loop is branchless apart from its exit condition: each comparison result selects the
+/// source pointer and advances the cursors arithmetically.
+///
 /// Cost: `O(n + m)` comparisons and `O(n + m)` moves.
 pub fn merge_up<T, const S: bool>([a, b]: [&mut [T]; 2], less: &mut impl FnMut(&T, &T) -> bool) {
     // Represents the gap to the left of `b`
@@ -54,6 +78,7 @@ pub fn merge_up<T, const S: bool>([a, b]: [&mut [T]; 2], less: &mut impl FnMut(&
         fn drop(&mut self) {
             unsafe {
                 write::<_, S>(self.0.add(self.3), self.2.add(self.3 + self.4), self.1 - self.3);
+                metrics::moved((1 + S as usize) * (self.1 - self.3));
             }
         }
     }
@@ -69,11 +94,13 @@ pub fn merge_up<T, const S: bool>([a, b]: [&mut [T]; 2], less: &mut impl FnMut(&
             let right = less(&*r, &*l);
             [gap.3, gap.4] = [gap.3 + !right as usize, gap.4 + right as usize];
             write::<_, S>(if right { r } else { l }, dst.add(gap.3 + gap.4 - 1), 1);
+            metrics::moved(1 + S as usize);
         }
     }
 }
 
-/// Merge `a` and `b` with the gap to the right of `a` and building the result leftwards.
+/// Merge `a` and `b` with the gap to the right of `a` and building the result leftwards. See
+/// [`merge_up`] regarding branches.
 ///
 /// Cost: `O(n + m)` comparisons and `O(n + m)` moves.
 pub fn merge_down<T, const S: bool>([a, b]: [&mut [T]; 2], less: &mut impl FnMut(&T, &T) -> bool) {
@@ -83,6 +110,7 @@ pub fn merge_down<T, const S: bool>([a, b]: [&mut [T]; 2], less: &mut impl FnMut
     impl<T, const S: bool> core::ops::Drop for Gap<T, S> {
         fn drop(&mut self) {
             unsafe { write::<_, S>(self.1, self.0.add(self.2), self.3); }
+            metrics::moved((1 + S as usize) * self.3);
         }
     }
 
@@ -96,11 +124,97 @@ pub fn merge_down<T, const S: bool>([a, b]: [&mut [T]; 2], less: &mut impl FnMut
             let left = less(&*r, &*l);
             [gap.2, gap.3] = [gap.2 - left as usize, gap.3 - !left as usize];
             write::<_, S>(if left { l } else { r }, a.add(gap.2 + gap.3), 1);
+            metrics::moved(1 + S as usize);
         }
     }
 }
 
-/// Merge `a` and `b` by rotating `b` into `a`, assuming `b.len() <= a.len()`.
+/// Merge the adjacent runs `a` and `b` by swapping the shorter of them into `buf` and merging it
+/// back with swaps, so that `buf` ends up holding its original elements in an unspecified order.
+///
+/// Cost: `O(n + m)` comparisons and `O(n + m)` moves.
+///
+/// # Safety
+///
+/// `b` must directly follow `a` in memory, and `buf` must be valid for `min(n, m)` elements and not
+/// overlap either run.
+pub unsafe fn merge_swapping<T, F: FnMut(&T, &T) -> bool>(
+    [a, b]: [&mut [T]; 2], buf: *mut T, less: &mut F,
+) {
+    metrics::swapped(a.len().min(b.len()));
+    if a.len() <= b.len() {
+        merge_up::<_, true>([move_slice::<_, true>(buf, a), b], less);
+    } else {
+        merge_down::<_, true>([a, move_slice::<_, true>(buf, b)], less);
+    }
+}
+
+/// Merge clones of `a` and `b` into a new vector.
+///
+/// Cost: `O(n + m)` comparisons and `O(n + m)` moves.
+#[cfg(feature = "alloc")]
+pub fn merge_cloned<T: Clone, F: FnMut(&T, &T) -> bool>(
+    a: &[T], b: &[T], less: &mut F,
+) -> alloc::vec::Vec<T> {
+    debug_assert!((1..a.len()).all(|i| !less(&a[i], &a[i - 1])), "`a` is not sorted");
+    debug_assert!((1..b.len()).all(|i| !less(&b[i], &b[i - 1])), "`b` is not sorted");
+
+    let [n, m] = [a.len(), b.len()];
+    let mut res = a.to_vec();
+    let mut tail = b.to_vec();
+    res.reserve_exact(m);
+
+    unsafe {
+        // The merge leaves every element in `res` even if `less` panics, so `tail` gives up its
+        // elements beforehand
+        tail.set_len(0);
+        res.set_len(n + m);
+        let [a, b] = [(res.as_mut_ptr(), n), (tail.as_mut_ptr(), m)]
+            .map(|(s, len)| core::slice::from_raw_parts_mut(s, len));
+        merge_down::<_, false>([a, b], less);
+    }
+
+    res
+}
+
+/// Call `emit` on every element of `a` and `b` in merged order, without moving either. Equal
+/// elements from `a` are emitted before those from `b`.
+///
+/// Cost: `O(n + m)` comparisons.
+pub fn merge_each<T, F: FnMut(&T, &T) -> bool>(
+    a: &[T], b: &[T], less: &mut F, emit: &mut impl FnMut(&T),
+) {
+    merge_each_bounded(a, b, a.len() + b.len(), less, emit);
+}
+
+/// Call `emit` on the first `limit` elements of `a` and `b` in merged order, or on all of them if
+/// there are fewer, as [`merge_each`] does. Return the lengths of the tails of `a` and `b` that
+/// were not emitted.
+///
+/// Cost: `O(min(limit, n + m))` comparisons.
+pub fn merge_each_bounded<T, F: FnMut(&T, &T) -> bool>(
+    a: &[T], b: &[T], limit: usize, less: &mut F, emit: &mut impl FnMut(&T),
+) -> [usize; 2] {
+    debug_assert!((1..a.len()).all(|i| !less(&a[i], &a[i - 1])), "`a` is not sorted");
+    debug_assert!((1..b.len()).all(|i| !less(&b[i], &b[i - 1])), "`b` is not sorted");
+
+    let [mut i, mut j] = [0, 0];
+    while i != a.len() && j != b.len() && i + j != limit {
+        let right = less(&b[j], &a[i]);
+        emit(if right { &b[j] } else { &a[i] });
+        [i, j] = [i + !right as usize, j + right as usize];
+    }
+
+    // At most one run is left, so we take the rest of the limit from it
+    let rest = limit - (i + j);
+    let [ta, tb] = [(a.len() - i).min(rest), (b.len() - j).min(rest)];
+    a[i..i + ta].iter().chain(&b[j..j + tb]).for_each(emit);
+    [a.len() - i - ta, b.len() - j - tb]
+}
+
+/// Merge `a` and `b` by rotating `b` into `a`, assuming `b.len() <= a.len()`. Each element of `a`
+/// that passes the last element of `b` is found by galloping from the end of `a`, so a short run of
+/// them costs few comparisons.
 ///
 /// Cost: `O(m log n/m + m)` comparisons and `O(n + m^2)` moves.
 pub fn merge_left<T, F: FnMut(&T, &T) -> bool>([a, b]: [&mut [T]; 2], less: &mut F) {
@@ -108,8 +222,10 @@ pub fn merge_left<T, F: FnMut(&T, &T) -> bool>([a, b]: [&mut [T]; 2], less: &mut
 
     unsafe {
         while m != 0 {
-            let len = n - search::binary(a, n, a.add(n + m - 1), &mut |x, y| !less(y, x));
+            let key = a.add(n + m - 1);
+            let len = n - gallop_left(n, |i| !less(&*key, &*a.add(i)));
             rotate(a.add(n - len), len + m, len);
+            metrics::moved(len + m);
             n -= len;
 
             if n == 0 {
@@ -121,6 +237,40 @@ pub fn merge_left<T, F: FnMut(&T, &T) -> bool>([a, b]: [&mut [T]; 2], less: &mut
     }
 }
 
+/// Return the first index in `0..n` at which `pred` is `false`, or `n` if there is none, assuming
+/// `pred` is `true` and then `false`. Indices are probed at distances of 1, 2, 4, ... from `n`
+/// before a binary search, so this takes `O(log (n - i))` calls to `pred` for a result of `i`.
+pub fn gallop_left(n: usize, mut pred: impl FnMut(usize) -> bool) -> usize {
+    let [mut end, mut step] = [n, 1];
+    while step <= end {
+        if pred(end - step) {
+            let start = end - step + 1;
+            return start + lower_bound::binary(end - start, |i| pred(start + i));
+        }
+
+        end -= step;
+        step *= 2;
+    }
+
+    lower_bound::binary(end, pred)
+}
+
+/// Return the same index as [`gallop_left`], probing indices 0, 2, 6, 14, ... from the start
+/// instead, so this takes `O(log i)` calls to `pred` for a result of `i`.
+pub fn gallop_right(n: usize, mut pred: impl FnMut(usize) -> bool) -> usize {
+    let [mut start, mut step] = [0, 1];
+    while step <= n - start {
+        if !pred(start + step - 1) {
+            return start + lower_bound::binary(step - 1, |i| pred(start + i));
+        }
+
+        start += step;
+        step *= 2;
+    }
+
+    start + lower_bound::binary(n - start, |i| pred(start + i))
+}
+
 /// Merge `a` and `b` by rotating `a` into `b`, assuming `a.len() <= b.len()`. Return the lengths of
 /// the tails of `a` and `b`.
 ///
@@ -134,6 +284,7 @@ pub fn merge_right<T, F: FnMut(&T, &T) -> bool>([a, b]: [&mut [T]; 2], less: &mu
         while n != 0 {
             let index = search::binary(r.sub(m), m, r.sub(m + n), less);
             rotate(r.sub(m + n), n + index, n);
+            metrics::moved(n + index);
             m -= index;
 
             if m == 0 {
@@ -147,13 +298,134 @@ pub fn merge_right<T, F: FnMut(&T, &T) -> bool>([a, b]: [&mut [T]; 2], less: &mu
     }
 }
 
-/// Merge `a` and `b` in-place using rotations.
+/// Merge `a` and `b` by splitting the longer slice in half, rotating the split point into place,
+/// and recursing on both sides. This is preferable to [`merge_left`] and [`merge_right`] when the
+/// shorter slice is not much shorter than the longer one.
 ///
-/// Cost: See [`merge_left`] and [`merge_right`].
+/// Both sides are merged with [`merge_with_partial_buffer`], so those that fit in `ext` use it.
+///
+/// Cost: `O(m log (n/m + 1))` comparisons and `O((n + m) log m)` moves, where `m <= n`.
+pub fn merge_recursive<T, F: FnMut(&T, &T) -> bool>(
+    [a, b]: [&mut [T]; 2], ext: &mut [T], less: &mut F,
+) {
+    let [(a, n), (_, m)] = [a, b].map(RawMut::raw_mut);
+
+    unsafe {
+        // Move the middle element of the longer slice into its final position at `i + j`, where `i`
+        // and `j` count the elements of `a` and `b` that precede it
+        let (i, j) = if n >= m {
+            let i = n / 2;
+            let j = search::binary(a.add(n), m, a.add(i), less);
+            rotate(a.add(i), n - i + j, n - i);
+            (i, j)
+        } else {
+            let j = m / 2;
+            let i = search::binary(a, n, a.add(n + j), &mut |x, y| !less(y, x));
+            rotate(a.add(i), n - i + j + 1, n - i);
+            (i, j)
+        };
+        metrics::moved(n - i + j + (n < m) as usize);
+
+        // Merge the elements on either side of the middle element
+        let (l, r) = a.crop(0..i + j).split_at_mut(i);
+        merge_with_partial_buffer([l, r], ext, less);
+        let (l, r) = a.crop(i + j + 1..n + m).split_at_mut(n - i - (n >= m) as usize);
+        merge_with_partial_buffer([l, r], ext, less);
+    }
+}
+
+/// Merge `a` and `b` in-place using rotations, choosing [`merge_left`] or [`merge_right`] when one
+/// slice is much shorter than the other and [`merge_recursive`] otherwise.
+///
+/// Cost: `O(m log (n/m + 1))` comparisons and `O(n + min(m^2, (n + m) log m))` moves, where
+/// `m <= n`.
 pub fn merge_in_place<T, F: FnMut(&T, &T) -> bool>([a, b]: [&mut [T]; 2], less: &mut F) {
-    if a.len() <= b.len() {
-        merge_right([a, b], less);
+    merge_with_partial_buffer([a, b], &mut [], less)
+}
+
+/// Merge `a` and `b` with `ext` as an external buffer if it can hold the shorter slice. Otherwise,
+/// merge them as [`merge_in_place`] does, except that every smaller merge that `ext` can hold uses
+/// it. A buffer of any length helps, as only the pieces too long for it are merged by rotation.
+///
+/// Cost: `O(m log (n/m + 1))` comparisons and `O(n + m + min(m^2, (n + m) log (m/b + 1)))` moves,
+/// where `m <= n` and `b = ext.len()`.
+pub fn merge_with_partial_buffer<T, F: FnMut(&T, &T) -> bool>(
+    [a, b]: [&mut [T]; 2], ext: &mut [T], less: &mut F,
+) {
+    let [n, m] = [a.len(), b.len()];
+    let short = n.min(m);
+
+    if short <= ext.len() {
+        // `ext` holds the shorter run, and our caller passes adjacent runs
+        unsafe { ext.merge_unchecked([a, b], less); }
+    } else if short <= 1 || short <= (n + m) / short {
+        if n <= m {
+            merge_right([a, b], less);
+        } else {
+            merge_left([a, b], less);
+        }
     } else {
-        merge_left([a, b], less);
+        merge_recursive([a, b], ext, less);
+    }
+}
+
+/// Stably partition `v` so that every element satisfying `pred` comes first, partitioning pieces
+/// that fit in `ext` directly and joining them with rotations. Return the number of elements
+/// satisfying `pred`.
+///
+/// Cost: `O(n)` calls to `pred` and `O(n log (n/b + 1))` moves, where `b = ext.len()`.
+pub fn partition<T>(v: &mut [T], ext: &mut [T], pred: &mut impl FnMut(&T) -> bool) -> usize {
+    let n = v.len();
+    if n <= 1 {
+        return (n != 0 && pred(&v[0])) as usize;
+    }
+
+    if n <= ext.len() {
+        return partition_buffered(v, ext, pred);
+    }
+
+    // Partition both halves, then rotate the middle to join them
+    let (a, b) = v.split_at_mut(n / 2);
+    let [i, j] = [partition(a, ext, pred), partition(b, ext, pred)];
+    unsafe { rotate(v.as_mut_ptr().add(i), n / 2 - i + j, n / 2 - i); }
+    metrics::moved(n / 2 - i + j);
+    i + j
+}
+
+// Stably partition `v` by moving the elements not satisfying `pred` into `ext`, assuming
+// `v.len() <= ext.len()`.
+//
+// Cost: `O(n)` calls to `pred` and `O(n)` moves.
+fn partition_buffered<T>(v: &mut [T], ext: &mut [T], pred: &mut impl FnMut(&T) -> bool) -> usize {
+    // Represents the gap between the elements kept in `v` and the unvisited elements, which is
+    // exactly as long as the number of elements moved into `ext`
+    struct Gap<T>(*mut T, *mut T, usize, usize);
+
+    impl<T> core::ops::Drop for Gap<T> {
+        fn drop(&mut self) {
+            unsafe { write::<_, false>(self.1, self.0.add(self.2), self.3); }
+            metrics::moved(self.3);
+        }
+    }
+
+    let [(s, n), (buf, _)] = [v, ext].map(RawMut::raw_mut);
+
+    unsafe {
+        let mut gap = Gap(s, buf, 0, 0);
+
+        for i in 0..n {
+            let cur = s.add(i);
+            if pred(&*cur) {
+                write::<_, false>(cur, s.add(gap.2), (gap.3 != 0) as usize);
+                metrics::moved((gap.3 != 0) as usize);
+                gap.2 += 1;
+            } else {
+                write::<_, false>(cur, buf.add(gap.3), 1);
+                metrics::moved(1);
+                gap.3 += 1;
+            }
+        }
+
+        gap.2
     }
 }
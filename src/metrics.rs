@@ -0,0 +1,82 @@
+//! Counters for the work done by a sort. Without the `metrics` feature, every function here is a
+//! no-op that compiles away.
+
+#[cfg(feature = "metrics")]
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+#[cfg(feature = "metrics")]
+static COMPARISONS: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "metrics")]
+static MOVES: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts of the work done by a sort.
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SortStats {
+    /// The number of calls to the comparison function.
+    pub comparisons: usize,
+
+    /// The number of element writes. A swap counts as two writes, and a rotation counts as one
+    /// write per element rotated.
+    pub moves: usize,
+}
+
+/// Return the counts of work done by the most recent sort. The counters are global rather than
+/// per-thread, so sorts running concurrently are counted together.
+///
+/// ```
+/// # #[cfg(feature = "metrics")] {
+/// let n: usize = 1 << 12;
+/// let log = n.ilog2() as usize;
+/// let checks = if cfg!(feature = "check-order") { 2 } else { 0 };
+///
+/// // Sorted input is a single natural run: 1 comparison of its ends, `n - 1` to find the run, and
+/// // no moves
+/// let config = aerosort::SortConfig::new().natural_runs(true);
+/// let mut v: Vec<usize> = (0..n).collect();
+/// config.sort_with_by(&mut v, &mut vec![0; n / 2][..], usize::cmp);
+/// let stats = aerosort::last_sort_stats();
+/// assert!(stats.comparisons <= n + checks * n);
+/// assert!(stats.moves == 0 || checks > 0);
+///
+/// // Random input takes around `n log n` comparisons, and as many moves with a buffer of `n / 2`
+/// let mut v: Vec<usize> = (0..n).map(|i| i * 7919 % n).collect();
+/// aerosort::sort_with(&mut v, &mut vec![0; n / 2][..]);
+/// let stats = aerosort::last_sort_stats();
+/// assert!(v.iter().copied().eq(0..n));
+/// assert!(stats.comparisons < (log + checks) * n);
+/// assert!(stats.moves < 2 * (log + checks) * n);
+/// # }
+/// ```
+#[cfg(feature = "metrics")]
+pub fn last_sort_stats() -> SortStats {
+    SortStats { comparisons: COMPARISONS.load(Relaxed), moves: MOVES.load(Relaxed) }
+}
+
+/// Reset the counters at the start of a sort.
+#[inline(always)]
+pub fn reset() {
+    #[cfg(feature = "metrics")]
+    [&COMPARISONS, &MOVES].iter().for_each(|counter| counter.store(0, Relaxed));
+}
+
+/// Count one comparison.
+#[inline(always)]
+pub fn compared() {
+    #[cfg(feature = "metrics")]
+    COMPARISONS.fetch_add(1, Relaxed);
+}
+
+/// Count `count` element writes.
+#[inline(always)]
+pub fn moved(_count: usize) {
+    #[cfg(feature = "metrics")]
+    MOVES.fetch_add(_count, Relaxed);
+}
+
+/// Count a swap of `count` pairs of elements.
+#[inline(always)]
+pub fn swapped(count: usize) {
+    moved(2 * count);
+}
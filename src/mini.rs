@@ -1,12 +1,136 @@
 use core::ptr;
 
+use crate::metrics;
+
+use sort_util::op::search;
 use sort_util::RawMut;
 
+/// A sort for the short pieces that a merge sort starts from, and for slices too short to merge.
+/// See [`crate::sort_with_base`].
+///
+/// A base sort may sort however it likes, as long as it leaves each piece sorted. This one sorts
+/// each piece descending and then reverses it:
+///
+/// ```
+/// use aerosort::{BaseSort, InsertionSort};
+///
+/// struct Reversing;
+///
+/// impl<T> BaseSort<T> for Reversing {
+///     fn sort<F: FnMut(&T, &T) -> bool>(&mut self, v: &mut [T], less: &mut F) {
+///         InsertionSort.sort(v, &mut |x, y| less(y, x));
+///         v.reverse();
+///     }
+/// }
+///
+/// let mut v: Vec<u32> = (0..1000).map(|i| i * 7919 % 1009).collect();
+/// aerosort::sort_with_base(&mut v, Reversing);
+/// assert!(v.windows(2).all(|w| w[0] <= w[1]));
+/// ```
+///
+/// A base sort that leaves a piece unsorted is caught:
+///
+/// ```should_panic
+/// struct Reverse;
+///
+/// impl<T> aerosort::BaseSort<T> for Reverse {
+///     fn sort<F: FnMut(&T, &T) -> bool>(&mut self, v: &mut [T], _: &mut F) {
+///         v.reverse();
+///     }
+/// }
+///
+/// aerosort::sort_with_base(&mut [1, 2, 3], Reverse);
+/// ```
+pub trait BaseSort<T> {
+    /// Sort `v` by `less`. This must be stable for the sort calling it to be stable, and must leave
+    /// `v` sorted, which [`crate::sort_with_base`] checks.
+    fn sort<F: FnMut(&T, &T) -> bool>(&mut self, v: &mut [T], less: &mut F);
+}
+
+impl<T, B: BaseSort<T> + ?Sized> BaseSort<T> for &mut B {
+    fn sort<F: FnMut(&T, &T) -> bool>(&mut self, v: &mut [T], less: &mut F) {
+        (**self).sort(v, less)
+    }
+}
+
+/// The default [`BaseSort`], a guarded insertion sort that starts from the natural run at the front
+/// of the slice, reversing it first if it is strictly descending. Sorting a slice that is already
+/// sorted or reversed then takes `n - 1` comparisons and at most `n` moves.
+///
+/// ```
+/// let mut v: Vec<u32> = (0..64).rev().collect();
+/// let mut comparisons = 0;
+/// aerosort::sort_by(&mut v, |x, y| { comparisons += 1; x.cmp(y) });
+/// assert!(v.iter().copied().eq(0..64));
+/// assert!(comparisons < 200);    // rather than 2016, inserting one element at a time
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InsertionSort;
+
+impl<T> BaseSort<T> for InsertionSort {
+    fn sort<F: FnMut(&T, &T) -> bool>(&mut self, v: &mut [T], less: &mut F) {
+        if v.len() < 2 {
+            return;
+        }
+
+        let (len, descending) = crate::runs::natural_run(v, less);
+        if descending {
+            v[..len].reverse();
+            metrics::swapped(len / 2);
+        }
+        insertion_sort_from(v, len, less);
+    }
+}
+
+/// A [`BaseSort`] that sorts with a binary insertion sort, which takes `O(log n)` comparisons per
+/// element rather than `O(n)`, for comparison functions costly enough to be worth the moves.
+///
+/// ```
+/// use aerosort::{BinaryInsertionSort, InsertionSort};
+///
+/// let v: Vec<u32> = (0..1000).map(|i| i * 7919 % 1009).collect();
+/// let [mut linear, mut binary] = [0, 0];
+/// aerosort::sort_with_base_by(&mut v.clone(), InsertionSort, |x, y| { linear += 1; x.cmp(y) });
+///
+/// let mut w = v.clone();
+/// aerosort::sort_with_base_by(&mut w, BinaryInsertionSort, |x, y| { binary += 1; x.cmp(y) });
+/// assert!(w.windows(2).all(|w| w[0] <= w[1]));
+/// assert!(binary < linear);
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BinaryInsertionSort;
+
+impl<T> BaseSort<T> for BinaryInsertionSort {
+    fn sort<F: FnMut(&T, &T) -> bool>(&mut self, v: &mut [T], less: &mut F) {
+        binary_insertion_sort(v, less);
+    }
+}
+
+/// A [`BaseSort`] that checks that `B` leaves every slice sorted.
+///
+/// Cost: `n - 1` comparisons per slice of length `n`, on top of those of `B`.
+pub struct Checked<B>(pub B);
+
+impl<T, B: BaseSort<T>> BaseSort<T> for Checked<B> {
+    fn sort<F: FnMut(&T, &T) -> bool>(&mut self, v: &mut [T], less: &mut F) {
+        self.0.sort(v, less);
+        assert!((1..v.len()).all(|i| !less(&v[i], &v[i - 1])), "base sort left `v` unsorted");
+    }
+}
+
 /// Sort `v` with a guarded insertion sort.
 ///
 /// Cost: `O(n^2)` comparisons and `O(n^2)` moves.
-#[inline(never)]
+#[inline(always)]
 pub fn insertion_sort_safe<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], less: &mut F) {
+    insertion_sort_from(v, 1, less);
+}
+
+/// Sort `v` with a guarded insertion sort, assuming its first `sorted` elements are sorted.
+///
+/// Cost: `O(n^2)` comparisons and `O(n^2)` moves.
+#[inline(never)]
+pub fn insertion_sort_from<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], sorted: usize, less: &mut F) {
     use core::mem::ManuallyDrop;
 
     // Represents the slot created on each insertion
@@ -15,23 +139,148 @@ pub fn insertion_sort_safe<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], less: &mut
     impl<T> core::ops::Drop for Slot<T> {
         fn drop(&mut self) {
             unsafe { ptr::copy_nonoverlapping(&*self.0, self.1.add(self.2), 1); }
+            metrics::moved(1);
         }
     }
 
     let (s, n) = v.raw_mut();
 
-    for i in 1..n {
+    for i in sorted.max(1)..n {
         unsafe {
             let mut slot = Slot(ManuallyDrop::new(s.add(i).read()), s, i);
 
             while slot.2 != 0 && less(&slot.0, &*s.add(slot.2 - 1)) {
                 slot.2 -= 1;
                 ptr::copy_nonoverlapping(s.add(slot.2), s.add(slot.2 + 1), 1);
+                metrics::moved(1);
             }
         }
     }
 }
 
+/// Sort `v` with a sorting network of the fewest comparators known for its length, using branchless
+/// conditional swaps, assuming `v.len() <= 8`. Equal elements may be reordered.
+///
+/// Cost: at most 19 comparisons and 38 moves.
+#[inline]
+pub fn network_sort_small<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], less: &mut F) {
+    let s = v.as_mut_ptr();
+    let comparators = SMALL_NETWORKS[v.len()];
+
+    for &(i, j) in comparators {
+        unsafe { swap_if_less(s.add(i as usize), s.add(j as usize), less) }
+    }
+    metrics::moved(2 * comparators.len());
+}
+
+// The comparators of optimal-size sorting networks for each length up to 8 (Knuth, TAOCP 5.3.4),
+// with 0, 0, 1, 3, 5, 9, 12, 16, and 19 comparators.
+const SMALL_NETWORKS: [&[(u8, u8)]; 9] = [
+    &[],
+    &[],
+    &[(0, 1)],
+    &[(0, 2), (0, 1), (1, 2)],
+    &[(0, 2), (1, 3), (0, 1), (2, 3), (1, 2)],
+    &[(0, 3), (1, 4), (0, 2), (1, 3), (0, 1), (2, 4), (1, 2), (3, 4), (2, 3)],
+    &[
+        (0, 5), (1, 3), (2, 4), (1, 2), (3, 4), (0, 3), (2, 5), (0, 1), (2, 3), (4, 5), (1, 2),
+        (3, 4),
+    ],
+    &[
+        (0, 6), (2, 3), (4, 5), (0, 2), (1, 4), (3, 6), (0, 1), (2, 5), (3, 4), (1, 2), (4, 6),
+        (2, 3), (4, 5), (1, 2), (3, 4), (5, 6),
+    ],
+    &[
+        (0, 2), (1, 3), (4, 6), (5, 7), (0, 4), (1, 5), (2, 6), (3, 7), (0, 1), (2, 3), (4, 5),
+        (6, 7), (2, 4), (3, 5), (1, 4), (3, 6), (1, 2), (3, 4), (5, 6),
+    ],
+];
+
+// Swap the elements at `a` and `b` if the one at `b` is less, selecting pointers rather than
+// branching on the result. The comparison happens before anything moves, so a panicking `less`
+// leaves both in place.
+#[inline(always)]
+unsafe fn swap_if_less<T, F: FnMut(&T, &T) -> bool>(a: *mut T, b: *mut T, less: &mut F) {
+    let swap = less(&*b, &*a);
+    let tmp = core::mem::ManuallyDrop::new(ptr::read(if swap { a } else { b }));
+    ptr::copy(if swap { b } else { a }, a, 1);
+    ptr::copy_nonoverlapping(&*tmp, b, 1);
+}
+
+/// Sort `v` with a binary insertion sort.
+///
+/// Cost: `O(n log n)` comparisons and `O(n^2)` moves.
+#[inline(never)]
+pub fn binary_insertion_sort<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], less: &mut F) {
+    let (s, n) = v.raw_mut();
+
+    for i in 1..n {
+        unsafe {
+            // Insert after all elements not greater than the current one (for stability); no
+            // comparisons happen while the element is out of the slice, so we need no guard
+            let j = search::binary(s, i, s.add(i), &mut |x, y| !less(y, x));
+            let x = s.add(i).read();
+            ptr::copy(s.add(j), s.add(j + 1), i - j);
+            s.add(j).write(x);
+            metrics::moved(i - j + 1);
+        }
+    }
+}
+
+/// Stably sort `v` by the byte keys given by `key` with a counting sort, copying elements through
+/// `ext`, assuming `ext.len() >= v.len()`. Elements are only copied out of `v` after every key has
+/// been computed, so a panicking `key` leaves `v` untouched.
+///
+/// Cost: `2n` calls to `key` and `O(n)` moves.
+pub fn counting_sort<T>(v: &mut [T], ext: &mut [T], key: &mut impl FnMut(&T) -> u8) {
+    let mut offsets = [0; 256];
+    v.iter().for_each(|x| offsets[key(x) as usize] += 1);
+
+    // Nothing to do if every key is the same
+    let n = v.len();
+    if offsets.contains(&n) {
+        return;
+    }
+
+    // Turn counts into the starting offset of each key
+    let mut total = 0;
+    for offset in offsets.iter_mut() {
+        [*offset, total] = [total, total + *offset];
+    }
+
+    let [(s, _), (buf, _)] = [v, ext].map(RawMut::raw_mut);
+    unsafe {
+        for i in 0..n {
+            let offset = &mut offsets[key(&*s.add(i)) as usize];
+            ptr::copy_nonoverlapping(s.add(i), buf.add(*offset), 1);
+            *offset += 1;
+        }
+
+        ptr::copy_nonoverlapping(buf, s, n);
+        metrics::moved(2 * n);
+    }
+}
+
+/// Sort `v`, a slice of `u8` or `bool`, by counting its values, since values that compare equal
+/// are identical.
+///
+/// Cost: `O(n)` reads and writes.
+pub fn byte_counting_sort<T: Primitive>(v: &mut [T]) {
+    debug_assert!(T::BYTE && core::mem::size_of::<T>() == 1);
+
+    let mut counts = [0; 256];
+    v.iter().for_each(|x| counts[unsafe { core::mem::transmute_copy::<T, u8>(x) } as usize] += 1);
+
+    // Only bytes that occurred are written back, so every value written is valid for `T`
+    let mut start = 0;
+    for (byte, &count) in counts.iter().enumerate().filter(|(_, &count)| count != 0) {
+        let x = unsafe { core::mem::transmute_copy::<u8, T>(&(byte as u8)) };
+        v[start..start + count].fill(x);
+        start += count;
+    }
+    metrics::moved(v.len());
+}
+
 /// Sort `v` with heap sort.
 ///
 /// Cost: `O(n log n)` comparisons and `O(n log n)` moves.
@@ -42,33 +291,266 @@ pub fn heap_sort<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], less: &mut F) {
     // Source: https://github.com/Voultapher/tiny-sort-rs/blob/main/src/unstable.rs
     unsafe {
         (0..n / 2).rev().for_each(|i| sift_down(v, i, less));
+        sort_heap(v, less);
+    }
+}
+
+/// Replace the elements of `heap` with clones of the `heap.len()` least elements of `heap` and `v`
+/// together, in sorted order. Every element of `v` is compared with the greatest element kept so
+/// far, at the root of a heap, and only those less than it are cloned in.
+///
+/// Cost: `O(n log k)` comparisons and moves for `k = heap.len()`, and at most `n` clones.
+pub fn keep_least<T: Clone, F: FnMut(&T, &T) -> bool>(heap: &mut [T], v: &[T], less: &mut F) {
+    let k = heap.len();
+    if k == 0 {
+        return;
+    }
+
+    unsafe {
+        (0..k / 2).rev().for_each(|i| sift_down(heap, i, less));
 
-        for i in (1..n).rev() {
-            v.swap(0, i);
-            sift_down(&mut v[..i], 0, less);
+        for x in v {
+            if less(x, &heap[0]) {
+                heap[0] = x.clone();
+                sift_down(heap, 0, less);
+            }
         }
+
+        sort_heap(heap, less);
+    }
+}
+
+// Sort `v`, assuming it is a max-heap, by moving its root to the end one element at a time.
+unsafe fn sort_heap<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], less: &mut F) {
+    for i in (1..v.len()).rev() {
+        v.swap(0, i);
+        metrics::swapped(1);
+        sift_down(&mut v[..i], 0, less);
     }
 }
 
+// Restore the heap property of the subtree at `root`, assuming both of its subtrees are heaps. This
+// uses Floyd's bottom-up sift: descend to a leaf along the greater children without comparing
+// against the root, climb back to where the root belongs, then swap it down into place. Since the
+// root usually belongs near the bottom, this takes around `log n` comparisons instead of `2 log n`.
 #[inline(never)]
-unsafe fn sift_down<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], mut root: usize, less: &mut F) {
+unsafe fn sift_down<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], root: usize, less: &mut F) {
     let (s, n) = v.raw_mut();
 
-    loop {
-        let mut child = 2 * root + 1;
-        if child >= n {
-            return;
+    // Descend to a leaf along the greater children
+    let mut leaf = root;
+    while 2 * leaf + 2 < n {
+        leaf = 2 * leaf + 1;
+        leaf += less(&*s.add(leaf), &*s.add(leaf + 1)) as usize;
+    }
+    if 2 * leaf + 1 < n {
+        leaf = 2 * leaf + 1;
+    }
+
+    // Climb back to the deepest node on the path that is not less than the root
+    while leaf != root && less(&*s.add(leaf), &*s.add(root)) {
+        leaf = (leaf - 1) / 2;
+    }
+
+    // Swap the root down the path, moving every node above `leaf` up a level; in one-based indices,
+    // the ancestors of `leaf` are its prefixes in binary
+    let depth = (root + 1).leading_zeros() - (leaf + 1).leading_zeros();
+    for k in (0..depth).rev() {
+        ptr::swap(s.add(((leaf + 1) >> (k + 1)) - 1), s.add(((leaf + 1) >> k) - 1));
+    }
+    metrics::swapped(depth as usize);
+}
+
+/// A primitive type with a natural order in which values that compare equal are identical, so an
+/// unstable sort of these types is indistinguishable from a stable one.
+pub trait Primitive: Copy + sealed::Sealed {
+    /// Return `true` iff `self` is ordered before `other`.
+    fn lt(self, other: Self) -> bool;
+}
+
+mod sealed {
+    pub trait Sealed {
+        // Whether values are one byte wide and ordered by that byte, so they can be counted
+        const BYTE: bool = false;
+
+        // How values map to 32-bit keys that can be merged with SIMD instructions, if they do
+        #[cfg(feature = "x86-simd")]
+        const LANE: Option<crate::simd::Lane> = None;
+    }
+}
+
+macro_rules! impl_primitive {
+    ($($t:ty $(as $byte:ident)? $(: $lane:ident)? => |$x:ident, $y:ident| $lt:expr),* $(,)?) => {$(
+        impl sealed::Sealed for $t {
+            $(const $byte: bool = true;)?
+            $(
+                #[cfg(feature = "x86-simd")]
+                const LANE: Option<crate::simd::Lane> = Some(crate::simd::Lane::$lane);
+            )?
         }
+        impl Primitive for $t {
+            #[inline(always)]
+            fn lt(self, other: Self) -> bool {
+                let [$x, $y] = [self, other];
+                $lt
+            }
+        }
+    )*}
+}
+
+impl_primitive! {
+    bool as BYTE => |x, y| !x & y,
+    u8 as BYTE => |x, y| x < y,
+    u32: U32 => |x, y| x < y,
+    i32: I32 => |x, y| x < y,
+    u64 => |x, y| x < y,
+    i64 => |x, y| x < y,
+    f32: F32 => |x, y| x.total_cmp(&y).is_lt(),
+    f64 => |x, y| x.total_cmp(&y).is_lt(),
+}
 
-        if child + 1 < n {
-            child += less(&*s.add(child), &*s.add(child + 1)) as usize;
+/// A [`BaseSort`] for primitive types that sorts with the branchless sorting network of
+/// [`network_sort`], which beats insertion sort on short pieces of random values since it never
+/// mispredicts a branch. Slices of more than 32 elements, which are only passed when the whole
+/// slice is too short to merge, are insertion sorted instead.
+///
+/// The network is not stable, so this must only be given the natural order of `T` (see
+/// [`Primitive::lt`]), under which elements that compare equal are identical. It is not exported,
+/// since [`crate::sort_with_base_by`] would pair it with any comparison function.
+#[cfg(feature = "network")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NetworkSort;
+
+#[cfg(feature = "network")]
+impl<T: Primitive> BaseSort<T> for NetworkSort {
+    fn sort<F: FnMut(&T, &T) -> bool>(&mut self, v: &mut [T], less: &mut F) {
+        if v.len() <= 32 {
+            network_sort_by(v, less);
+        } else {
+            InsertionSort.sort(v, less);
         }
+    }
+}
 
-        if !less(&*s.add(root), &*s.add(child)) {
-            return;
+/// Sort `v` with Batcher's merge-exchange sorting network (Knuth, TAOCP 5.2.2, Algorithm M), using
+/// branchless conditional swaps.
+///
+/// Cost: `O(n log^2 n)` comparisons and `O(n log^2 n)` moves.
+#[cfg(feature = "network")]
+pub fn network_sort<T: Primitive>(v: &mut [T]) {
+    network_sort_by(v, &mut |x: &T, y: &T| x.lt(*y));
+}
+
+// Sort `v` with the network of `network_sort`, swapping two elements iff `less` orders them.
+#[cfg(feature = "network")]
+fn network_sort_by<T: Copy, F: FnMut(&T, &T) -> bool>(v: &mut [T], less: &mut F) {
+    let n = v.len();
+    if n < 2 {
+        return;
+    }
+
+    // Look up the comparators of short networks rather than enumerating them
+    if let Some((comparators, len)) = NETWORKS.get(n) {
+        for &(i, j) in &comparators[..*len] {
+            let [i, j] = [i as usize, j as usize];
+            let [x, y] = [v[i], v[j]];
+            [v[i], v[j]] = if less(&y, &x) { [y, x] } else { [x, y] };
+        }
+        metrics::moved(2 * len);
+        return;
+    }
+
+    let t = (usize::BITS - (n - 1).leading_zeros()) as usize;
+    let mut p = 1 << (t - 1);
+
+    while p > 0 {
+        let [mut q, mut r, mut d] = [1 << (t - 1), 0, p];
+
+        loop {
+            for i in (0..n - d).filter(|&i| i & p == r) {
+                let [x, y] = [v[i], v[i + d]];
+                [v[i], v[i + d]] = if less(&y, &x) { [y, x] } else { [x, y] };
+                metrics::moved(2);
+            }
+
+            if q == p {
+                break;
+            }
+
+            [d, q, r] = [q - p, q >> 1, p];
+        }
+
+        p >>= 1;
+    }
+}
+
+// The comparators of Batcher's merge-exchange sorting network for a length of at most 32, in
+// order, followed by the number of comparators.
+#[cfg(feature = "network")]
+type Network = ([(u8, u8); 191], usize);
+
+// The comparators of the networks for every length up to 32
+#[cfg(feature = "network")]
+static NETWORKS: [Network; 33] = {
+    let mut res = [network(0); 33];
+    let mut n = 2;
+    while n <= 32 {
+        res[n] = network(n);
+        n += 1;
+    }
+    res
+};
+
+// Compute the comparators that `network_sort` uses for a slice of length `n`, or none if `n > 32`.
+#[cfg(feature = "network")]
+const fn network(n: usize) -> Network {
+    let mut res = ([(0, 0); 191], 0);
+    if n < 2 || n > 32 {
+        return res;
+    }
+
+    let t = (usize::BITS - (n - 1).leading_zeros()) as usize;
+    let mut p = 1 << (t - 1);
+
+    while p > 0 {
+        let [mut q, mut r, mut d] = [1 << (t - 1), 0, p];
+
+        loop {
+            let mut i = 0;
+            while i < n - d {
+                if i & p == r {
+                    res.0[res.1] = (i as u8, (i + d) as u8);
+                    res.1 += 1;
+                }
+                i += 1;
+            }
+
+            if q == p {
+                break;
+            }
+
+            [d, q, r] = [q - p, q >> 1, p];
         }
 
-        ptr::swap(s.add(child), s.add(root));
-        root = child;
+        p >>= 1;
+    }
+
+    res
+}
+
+/// Sort `v` with the same sorting network as [`network_sort`], computing its comparators at compile
+/// time so that the network can be unrolled, assuming `N <= 32`.
+///
+/// Cost: `O(n log^2 n)` comparisons and `O(n log^2 n)` moves.
+#[cfg(feature = "network")]
+#[inline(always)]
+pub fn network_sort_array<T: Primitive, const N: usize>(v: &mut [T; N]) {
+    let (comparators, len) = const { network(N) };
+
+    for &(i, j) in &comparators[..len] {
+        let [i, j] = [i as usize, j as usize];
+        let [x, y] = [v[i], v[j]];
+        [v[i], v[j]] = if y.lt(x) { [y, x] } else { [x, y] };
     }
+    metrics::moved(2 * len);
 }
@@ -0,0 +1,84 @@
+use alloc::vec::Vec;
+use core::mem::MaybeUninit;
+use core::ptr;
+
+use crate::metrics;
+use crate::SortConfig;
+
+// Below this length, we sort or merge sequentially.
+const SEQUENTIAL_LEN: usize = 1 << 13;
+
+/// Sort `v` in parallel. Halves are sorted concurrently with [`crate::aero::sort_full`], then merged
+/// by splitting each merge into independent halves around a binary-searched pivot.
+///
+/// Merges only copy elements into a scratch buffer, and the result is copied back once a merge is
+/// complete, so `v` is never left without one of its elements if the comparison function panics.
+///
+/// Cost: `O(n log n)` comparisons and `O(n log n)` moves, with `O(n)` extra space.
+pub fn par_sort<T: Send, F: Fn(&T, &T) -> bool + Sync>(v: &mut [T], less: &F) {
+    let n = v.len();
+
+    // We keep the length of this vector at zero and only use its capacity, since copies of sorted
+    // elements are left behind that must never be dropped
+    let mut buf = Vec::<T>::with_capacity(n);
+    let buf = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr().cast(), n) };
+    sort_recursive(v, buf, less);
+}
+
+// Sort `v` with `buf` as scratch space, assuming `buf.len() == v.len()`.
+fn sort_recursive<T: Send, F: Fn(&T, &T) -> bool + Sync>(
+    v: &mut [T], buf: &mut [MaybeUninit<T>], less: &F,
+) {
+    let n = v.len();
+
+    if n <= SEQUENTIAL_LEN {
+        // Our external merges only ever read buffer slots they have written to
+        let ext = unsafe { &mut *(buf as *mut [MaybeUninit<T>] as *mut [T]) };
+        return crate::aero::sort_full(v, ext, &SortConfig::DEFAULT, &mut |x, y| less(x, y));
+    }
+
+    let (a, b) = v.split_at_mut(n / 2);
+    let (buf_a, buf_b) = buf.split_at_mut(n / 2);
+    rayon::join(|| sort_recursive(a, buf_a, less), || sort_recursive(b, buf_b, less));
+
+    // Merge into our buffer, then move everything back
+    merge_into([a, b], buf, less);
+    unsafe { ptr::copy_nonoverlapping(buf.as_ptr().cast(), v.as_mut_ptr(), n); }
+    metrics::moved(2 * n);
+}
+
+// Copy the merge of `a` and `b` into `dst`, assuming `dst.len() == a.len() + b.len()`.
+fn merge_into<T: Send, F: Fn(&T, &T) -> bool + Sync>(
+    [a, b]: [&mut [T]; 2], dst: &mut [MaybeUninit<T>], less: &F,
+) {
+    let [n, m] = [a.len(), b.len()];
+
+    if n + m <= SEQUENTIAL_LEN {
+        let dst = dst.as_mut_ptr().cast::<T>();
+        let [mut i, mut j] = [0, 0];
+
+        unsafe {
+            while i != n && j != m {
+                let right = less(&b[j], &a[i]);
+                ptr::copy_nonoverlapping(if right { &b[j] } else { &a[i] }, dst.add(i + j), 1);
+                [i, j] = [i + !right as usize, j + right as usize];
+            }
+
+            ptr::copy_nonoverlapping(a.as_ptr().add(i), dst.add(i + j), n - i);
+            ptr::copy_nonoverlapping(b.as_ptr().add(j), dst.add(i + j), m - j);
+        }
+
+        return;
+    }
+
+    // Split around the middle of the longer side, sending ties from `a` left (for stability)
+    let [i, j] = if n >= m {
+        [n / 2, b.partition_point(|y| less(y, &a[n / 2]))]
+    } else {
+        [a.partition_point(|x| !less(&b[m / 2], x)), m / 2]
+    };
+
+    let [(a, c), (b, d)] = [a.split_at_mut(i), b.split_at_mut(j)];
+    let (dst_l, dst_r) = dst.split_at_mut(i + j);
+    rayon::join(|| merge_into([a, b], dst_l, less), || merge_into([c, d], dst_r, less));
+}
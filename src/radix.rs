@@ -0,0 +1,219 @@
+use core::ptr;
+
+use crate::metrics;
+
+// The number of bits in each digit. Fewer, wider passes scatter less often, which matters more
+// than the size of the histograms (about 100 KiB on the stack in total).
+const DIGIT_BITS: usize = 11;
+const DIGITS: usize = 64usize.div_ceil(DIGIT_BITS);
+
+/// Stably sort `v` by the 64-bit keys given by `key` with a least significant digit radix sort,
+/// moving elements back and forth between `v` and `ext`, assuming `ext.len() >= v.len()`. Passes
+/// over digits that are the same for every key are skipped.
+///
+/// Cost: `O(n)` moves.
+pub fn radix_sort<T: Copy>(v: &mut [T], ext: &mut [T], key: impl Fn(T) -> u64) {
+    let n = v.len();
+    let ext = &mut ext[..n];
+
+    let digit = |k: u64, d: usize| (k >> (DIGIT_BITS * d)) as usize & ((1 << DIGIT_BITS) - 1);
+
+    // Count every digit of every key in a single pass
+    let mut counts = [[0; 1 << DIGIT_BITS]; DIGITS];
+    for &x in v.iter() {
+        let k = key(x);
+        (0..DIGITS).for_each(|d| counts[d][digit(k, d)] += 1);
+    }
+
+    let [mut src, mut dst] = [v, ext];
+    let mut swapped = false;
+    for (d, offsets) in counts.iter_mut().enumerate() {
+        // Skip digits that every key shares
+        if offsets.contains(&n) {
+            continue;
+        }
+
+        let mut total = 0;
+        for offset in offsets.iter_mut() {
+            [*offset, total] = [total, total + *offset];
+        }
+
+        // Every offset stays below `n`, since the counts of each digit sum to `n`
+        let out = dst.as_mut_ptr();
+        for &x in src.iter() {
+            let offset = &mut offsets[digit(key(x), d)];
+            unsafe { out.add(*offset).write(x); }
+            *offset += 1;
+        }
+        metrics::moved(n);
+
+        [src, dst] = [dst, src];
+        swapped = !swapped;
+    }
+
+    // The sorted elements end up in `ext` after an odd number of passes
+    if swapped {
+        dst.copy_from_slice(src);
+        metrics::moved(n);
+    }
+}
+
+/// Map `x` to a key whose unsigned order is the order of `x`.
+pub fn i64_key(x: i64) -> u64 {
+    x as u64 ^ 1 << 63
+}
+
+/// Map `x` to a key whose unsigned order is the total order of `x` (see [`f64::total_cmp`]).
+pub fn f64_key(x: f64) -> u64 {
+    let bits = x.to_bits() as i64;
+    i64_key(bits ^ (((bits >> 63) as u64) >> 1) as i64)
+}
+
+// The length of a bucket of byte keys from which it is split by another pass rather than finished
+// by comparing the rest of its keys.
+const BYTES_CUTOFF: usize = 32;
+
+/// Stably sort `v` by the byte strings given by `key`, in lexicographic order, with a most
+/// significant digit radix sort starting from byte `depth` of every key, copying elements through
+/// `ext`, assuming `ext.len() >= v.len()` and that every key shares its first `depth` bytes. A key
+/// sorts before every key it is a prefix of, and equal keys keep their order. Buckets of at most 32
+/// elements are finished with a binary insertion sort comparing the rest of their keys.
+///
+/// Every bucket but the longest is sorted recursively and the longest one in a loop, so the
+/// recursion is at most `log n` deep however long the keys are. Elements are only copied out of
+/// each bucket after every key in it has been computed, so a panicking `key` leaves `v` holding
+/// all of its elements.
+///
+/// Cost: `2n` calls to `key` and `2n` moves per pass, with one pass for every byte that some pair
+/// of keys in a bucket longer than 32 elements shares.
+pub fn byte_sort<T, F: FnMut(&T) -> &[u8]>(
+    mut v: &mut [T], ext: &mut [T], mut depth: usize, key: &mut F,
+) {
+    loop {
+        let n = v.len();
+        if n <= BYTES_CUTOFF {
+            let mut less = |x: &T, y: &T| key(x)[depth..] < key(y)[depth..];
+            return crate::mini::binary_insertion_sort(v, &mut less);
+        }
+
+        let mut counts = [0; 257];
+        v.iter().for_each(|x| counts[bucket(key(x), depth)] += 1);
+
+        // Skip bytes that every key shares, and stop once every key has ended
+        if counts.contains(&n) {
+            if counts[0] == n {
+                return;
+            }
+            depth += 1;
+            continue;
+        }
+
+        // Turn counts into the starting offset of each bucket
+        let mut starts = [0; 257];
+        let mut total = 0;
+        for (start, &count) in starts.iter_mut().zip(&counts) {
+            [*start, total] = [total, total + count];
+        }
+
+        let (s, buf) = (v.as_mut_ptr(), ext.as_mut_ptr());
+        let mut offsets = starts;
+        unsafe {
+            for i in 0..n {
+                let offset = &mut offsets[bucket(key(&*s.add(i)), depth)];
+                ptr::copy_nonoverlapping(s.add(i), buf.add(*offset), 1);
+                *offset += 1;
+            }
+
+            ptr::copy_nonoverlapping(buf, s, n);
+            metrics::moved(2 * n);
+        }
+
+        // The keys of bucket 0 are all equal, so sort every other bucket, saving the longest for
+        // the next iteration
+        let longest = (1..257).max_by_key(|&b| counts[b]).unwrap();
+        for b in (1..257).filter(|&b| b != longest && counts[b] > 1) {
+            byte_sort(&mut v[starts[b]..starts[b] + counts[b]], ext, depth + 1, key);
+        }
+
+        v = &mut core::mem::take(&mut v)[starts[longest]..starts[longest] + counts[longest]];
+        depth += 1;
+    }
+}
+
+// Return the bucket of `key` for a pass over byte `depth`: keys that end before it go in bucket 0,
+// before those that continue with any byte.
+fn bucket(key: &[u8], depth: usize) -> usize {
+    key.get(depth).map_or(0, |&b| b as usize + 1)
+}
+
+// The length of a bucket of 32-bit keys from which it is finished by insertion sort rather than
+// split by another pass.
+const FLAG_CUTOFF: usize = 32;
+
+/// Sort `v` by the 32-bit keys given by `key`, starting from the byte at bit `shift` of every key,
+/// with an in-place most significant digit radix sort (American flag sort), assuming every key
+/// shares its bits above `shift + 8`. Each pass swaps elements into the buckets of one byte, so
+/// elements with equal keys may end up in any order. Buckets of at most 32 elements are finished
+/// with insertion sort by key.
+///
+/// Every bucket but the longest is sorted recursively and the longest one in a loop, so the
+/// recursion is at most 4 deep. Elements are only ever swapped, so a panicking `key` leaves `v`
+/// holding all of its elements.
+///
+/// Cost: `2n` calls to `key` and at most `n` swaps per pass, with at most 4 passes.
+pub fn flag_sort<T, F: FnMut(&T) -> u32>(mut v: &mut [T], mut shift: u32, key: &mut F) {
+    loop {
+        let n = v.len();
+        if n <= FLAG_CUTOFF {
+            return crate::mini::insertion_sort_safe(v, &mut |x, y| key(x) < key(y));
+        }
+
+        let digit = |k: u32| (k >> shift) as usize & 0xff;
+        let mut counts = [0; 256];
+        v.iter().for_each(|x| counts[digit(key(x))] += 1);
+
+        // Skip bytes that every key shares, and stop once every byte has been sorted
+        if counts.contains(&n) {
+            if shift == 0 {
+                return;
+            }
+            shift -= 8;
+            continue;
+        }
+
+        // Turn counts into the starting offset of each bucket
+        let mut starts = [0; 256];
+        let mut total = 0;
+        for (start, &count) in starts.iter_mut().zip(&counts) {
+            [*start, total] = [total, total + count];
+        }
+
+        // Swap the element at the head of each bucket into the bucket it belongs to until the head
+        // holds an element of its own bucket, then move on
+        let mut heads = starts;
+        for b in 0..256 {
+            let end = starts[b] + counts[b];
+            while heads[b] < end {
+                let d = digit(key(&v[heads[b]]));
+                if d != b {
+                    v.swap(heads[b], heads[d]);
+                    metrics::swapped(1);
+                }
+                heads[d] += 1;
+            }
+        }
+
+        if shift == 0 {
+            return;
+        }
+
+        // Sort every other bucket by the next byte, saving the longest for the next iteration
+        let longest = (0..256).max_by_key(|&b| counts[b]).unwrap();
+        for b in (0..256).filter(|&b| b != longest && counts[b] > 1) {
+            flag_sort(&mut v[starts[b]..starts[b] + counts[b]], shift - 8, key);
+        }
+
+        v = &mut core::mem::take(&mut v)[starts[longest]..starts[longest] + counts[longest]];
+        shift -= 8;
+    }
+}
@@ -0,0 +1,307 @@
+use core::ops::Range;
+
+use crate::merge::{gallop_left, merge_with_partial_buffer};
+use crate::metrics;
+use crate::mini::insertion_sort_from;
+use crate::trace::trace;
+use crate::SortConfig;
+
+/// Merge the consecutive sorted runs of `v` delimited by `boundaries`, using `ext` as an external
+/// buffer for every merge, or every part of a merge, that it can hold. Runs are merged pairwise in
+/// a balanced order, so every element takes part in `O(log k)` merges, where `k` is the number of
+/// runs.
+///
+/// Cost: `O(n log k)` comparisons and `O(n log k)` moves given a sufficient buffer; otherwise see
+/// [`crate::merge::merge_with_partial_buffer`].
+pub fn merge_runs<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], ext: &mut [T], boundaries: &[usize], less: &mut F,
+) {
+    let n = v.len();
+    check_boundaries(n, boundaries);
+
+    // Run `i` spans `start(i)..start(i + 1)`
+    let k = boundaries.len() + 1;
+    let start = |i: usize| match i {
+        0 => 0,
+        i if i == k => n,
+        i => boundaries[i - 1],
+    };
+
+    merge_range(v, ext, &start, 0..k, less);
+}
+
+/// Merge the consecutive runs of `v` delimited by `boundaries` as [`merge_runs`] does, where run
+/// `i` is sorted in descending order iff `descending[i]`. Descending runs are reversed first,
+/// keeping equal elements in their order.
+///
+/// Cost: `O(n)` comparisons and moves on top of [`merge_runs`].
+pub fn merge_directed_runs<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], ext: &mut [T], boundaries: &[usize], descending: &[bool], less: &mut F,
+) {
+    let n = v.len();
+    check_boundaries(n, boundaries);
+    assert_eq!(descending.len(), boundaries.len() + 1, "expected one direction per run");
+
+    let ends = boundaries.iter().copied().chain([n]);
+    let mut start = 0;
+    for (end, &descending) in ends.zip(descending) {
+        if descending {
+            reverse_stably(&mut v[start..end], less);
+        }
+        start = end;
+    }
+
+    merge_runs(v, ext, boundaries, less);
+}
+
+// Reverse `v`, assuming it is sorted in descending order, and then reverse every group of equal
+// elements back, so that they keep their order.
+fn reverse_stably<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], less: &mut F) {
+    v.reverse();
+    metrics::swapped(v.len() / 2);
+
+    let mut start = 0;
+    for i in 1..=v.len() {
+        if i == v.len() || less(&v[start], &v[i]) {
+            v[start..i].reverse();
+            metrics::swapped((i - start) / 2);
+            start = i;
+        }
+    }
+}
+
+// Panic unless `boundaries` is non-decreasing and within a slice of length `n`.
+fn check_boundaries(n: usize, boundaries: &[usize]) {
+    assert!(
+        boundaries.windows(2).all(|w| w[0] <= w[1]) && boundaries.iter().all(|&b| b <= n),
+        "run boundaries must be non-decreasing and within the slice",
+    );
+}
+
+// Merge the runs with indices in `runs`, where run `i` begins at `start(i)`.
+fn merge_range<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], ext: &mut [T], start: &impl Fn(usize) -> usize, runs: Range<usize>, less: &mut F,
+) {
+    if runs.len() <= 1 {
+        return;
+    }
+
+    let mid = runs.start + runs.len() / 2;
+    merge_range(v, ext, start, runs.start..mid, less);
+    merge_range(v, ext, start, mid..runs.end, less);
+
+    let [left, mid, right] = [start(runs.start), start(mid), start(runs.end)];
+    let (a, b) = v[left..right].split_at_mut(mid - left);
+    merge_with_partial_buffer([a, b], ext, less);
+}
+
+/// Return the length of the longest non-descending prefix of `v`.
+///
+/// Cost: `O(n)` comparisons.
+pub fn sorted_prefix<T, F: FnMut(&T, &T) -> bool>(v: &[T], less: &mut F) -> usize {
+    (1..v.len()).find(|&i| less(&v[i], &v[i - 1])).unwrap_or(v.len())
+}
+
+/// Sort `v` by inserting every element that is out of order into the sorted prefix before it,
+/// galloping back from the end of the prefix to find its place, while the elements shifted stay
+/// within `budget`. Once an insertion would exceed it, sort the rest of `v` with
+/// [`crate::aero::sort_full`] and merge it into the prefix.
+///
+/// An element displaced by `d` places costs `O(log d)` comparisons and `d` moves to insert, and
+/// an element displaced forwards costs 2 comparisons and 2 moves for every element that passes it.
+///
+/// Cost: `O(n + budget)` comparisons and moves, on top of the sort and merge if we give up.
+pub fn sort_nearly_sorted<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], mut budget: usize, less: &mut F,
+) {
+    let n = v.len();
+    for i in 1..n {
+        if !less(&v[i], &v[i - 1]) {
+            continue;
+        }
+
+        // Insert after every element of the prefix that is not greater, for stability
+        let j = gallop_left(i - 1, |k| !less(&v[i], &v[k]));
+        if i - j > budget {
+            trace!("sort_nearly_sorted: escalate n={n} at={i}");
+            crate::aero::sort_full(&mut v[i..], &mut [], &SortConfig::DEFAULT, less);
+            let (a, b) = v.split_at_mut(i);
+            return merge_with_partial_buffer([a, b], &mut [], less);
+        }
+
+        budget -= i - j;
+        v[j..=i].rotate_right(1);
+        metrics::moved(i - j + 1);
+    }
+}
+
+// The length up to which we extend short natural runs with insertion sort.
+const MIN_RUN: usize = 16;
+
+/// Sort `v` by finding its maximal non-descending and strictly descending runs (reversing the
+/// latter), extending short runs with insertion sort, and merging them with `merge` in the order
+/// chosen by powersort (Munro and Wild, 2018). Merges are nearly balanced, and every element takes
+/// part in `O(log r)` merges, where `r` is the number of runs.
+///
+/// A run that continues the one before it once made ascending, such as a descending run above an
+/// ascending one, is joined to it for a single comparison instead of being merged. Teeth that each
+/// descend but step upwards then sort in `n` comparisons and `n / 2` swaps.
+///
+/// Before finding each run and before each of the final merges, call `cancel` and give up if it
+/// returns `true`. Return `false` iff we gave up.
+///
+/// Cost: `O(n log r)` comparisons and moves on top of the cost of `merge`.
+pub fn sort_natural<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], less: &mut F, cancel: &mut impl FnMut() -> bool,
+    mut merge: impl FnMut([&mut [T]; 2], &mut F),
+) -> bool {
+    let n = v.len();
+
+    // Each entry holds the start of a pending run and the power of the boundary to its right.
+    // Powers strictly increase up the stack and lie in `0..64`, so 64 entries always suffice.
+    let mut stack = [(0, 0); 64];
+    let mut height = 0;
+
+    let [mut start, mut end] = [0, next_run(v, 0, less).0];
+    while end < n {
+        if cancel() {
+            return false;
+        }
+
+        let (next, natural) = next_run(v, end, less);
+
+        // Join the new run to the current one if they are already in order. Runs extended with
+        // insertion sort rarely join, so we spare random input the comparisons
+        if natural && joins(v, start..end, next, less) {
+            end = next;
+            continue;
+        }
+
+        let power = node_power(start, end, next, n);
+
+        // Merge pending runs that sit deeper in the merge tree than the new boundary
+        while height != 0 && stack[height - 1].1 >= power {
+            height -= 1;
+            let left = stack[height].0;
+            let (a, b) = v[left..end].split_at_mut(start - left);
+            merge([a, b], less);
+            start = left;
+        }
+
+        stack[height] = (start, power);
+        height += 1;
+        [start, end] = [end, next];
+    }
+
+    // Merge the remaining runs from right to left
+    while height != 0 {
+        if cancel() {
+            return false;
+        }
+
+        height -= 1;
+        let left = stack[height].0;
+        let (a, b) = v[left..n].split_at_mut(start - left);
+        merge([a, b], less);
+        start = left;
+    }
+
+    true
+}
+
+// Return whether the sorted runs `run` and `run.end..next` of `v` can be joined into one sorted
+// run, making it so. They join as they are if the new run starts no lower than the current one
+// ends. Otherwise, they join if the last element of the current run lies above the whole new run,
+// and the rest of the current run below it, by moving that element to the end: the peak between an
+// ascending run and a descending one belongs to the ascending run, so this joins an ascending run
+// to a higher descending one.
+fn joins<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], run: Range<usize>, next: usize, less: &mut F,
+) -> bool {
+    let Range { start, end } = run;
+    if !less(&v[end], &v[end - 1]) {
+        return true;
+    }
+
+    // The last element is the greatest of its run, so it stays in order with equal elements
+    let peak = end - start >= 2 && less(&v[next - 1], &v[end - 1]) && !less(&v[end], &v[end - 2]);
+    if peak {
+        v[end - 1..next].rotate_left(1);
+        metrics::moved(next - end + 1);
+    }
+    peak
+}
+
+// Find the natural run of `v` starting at `start`, make it ascending, and extend it to `MIN_RUN`
+// elements if possible. Return the end of the run, and whether it needed no extending.
+fn next_run<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], start: usize, less: &mut F,
+) -> (usize, bool) {
+    let rest = &mut v[start..];
+    let n = rest.len();
+    if n < 2 {
+        return (start + n, true);
+    }
+
+    let (len, descending) = natural_run(rest, less);
+    if descending {
+        rest[..len].reverse();
+        metrics::swapped(len / 2);
+    }
+
+    let end = len.max(MIN_RUN.min(n));
+    insertion_sort_from(&mut rest[..end], len, less);
+    (start + end, len == end)
+}
+
+/// Return the length of the natural run at the start of `v`, assuming `v.len() >= 2`, and whether
+/// it is strictly descending. Only strictly descending runs count as descending, so that reversing
+/// one keeps equal elements in order.
+///
+/// Cost: `O(len)` comparisons.
+pub fn natural_run<T, F: FnMut(&T, &T) -> bool>(v: &[T], less: &mut F) -> (usize, bool) {
+    if !less(&v[1], &v[0]) {
+        return (1 + sorted_prefix(&v[1..], less), false);
+    }
+
+    let mut len = 2;
+    while len < v.len() && less(&v[len], &v[len - 1]) {
+        len += 1;
+    }
+    (len, true)
+}
+
+/// Write the start of every natural run of `v` but the first to `out`, in order, as
+/// [`sort_natural`] finds them before extending short runs: maximal non-descending runs and
+/// strictly descending runs. Boundaries past the end of `out` are counted but not written. Return
+/// the number of boundaries.
+///
+/// Cost: `O(n)` comparisons.
+pub fn detect_runs<T, F: FnMut(&T, &T) -> bool>(
+    v: &[T], less: &mut F, out: &mut [usize],
+) -> usize {
+    let n = v.len();
+    let [mut start, mut count] = [0, 0];
+    while n - start >= 2 {
+        start += natural_run(&v[start..], less).0;
+        if start == n {
+            break;
+        }
+
+        if let Some(slot) = out.get_mut(count) {
+            *slot = start;
+        }
+        count += 1;
+    }
+
+    count
+}
+
+// Return the powersort power of the boundary between runs `start..mid` and `mid..end` of a slice
+// of length `n`: the depth at which the midpoints of both runs first fall on different sides of a
+// halving of `0..n`.
+fn node_power(start: usize, mid: usize, end: usize, n: usize) -> u32 {
+    // Scale both (doubled) midpoints by `2^63 / n`, so that they fit in a `u64`
+    let [a, b] = [start + mid, mid + end].map(|x| ((x as u128) << 63) / n as u128);
+    ((a ^ b) as u64).leading_zeros()
+}
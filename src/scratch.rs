@@ -0,0 +1,266 @@
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::mem::MaybeUninit;
+
+use sort_util::buffer::Empty;
+
+/// A reusable external buffer for sorting many slices in a row.
+///
+/// A scratch buffer either borrows a caller-provided slice, borrows uninitialized memory (including
+/// the capacity of a `heapless::Vec` with the `heapless` feature), or, with the `alloc` feature,
+/// owns an allocation that only ever grows. Sorting through the same scratch buffer repeatedly
+/// therefore allocates at most once per new maximum length.
+pub struct Scratch<'a, T> {
+    storage: Storage<'a, T>,
+}
+
+enum Storage<'a, T> {
+    Borrowed(&'a mut [T]),
+
+    // Borrowed capacity that holds no elements, for the same reason as below
+    Spare(&'a mut [MaybeUninit<T>]),
+
+    // We keep the length of this vector at zero and only use its capacity, since our merges leave
+    // copies of sorted elements behind that must never be dropped
+    #[cfg(feature = "alloc")]
+    Owned(Vec<T>),
+}
+
+impl<'a, T> Scratch<'a, T> {
+    /// Create a scratch buffer over the borrowed slice `buf`.
+    ///
+    /// ```
+    /// let mut buf = [0; 8];
+    /// let mut scratch = aerosort::Scratch::from_slice(&mut buf);
+    /// for mut v in [vec![3, 1, 2], vec![9, 7, 8, 6]] {
+    ///     scratch.sort(&mut v);
+    ///     assert!(v.windows(2).all(|w| w[0] <= w[1]));
+    /// }
+    /// ```
+    ///
+    /// Merges leave bitwise copies of elements behind in `buf`, which its owner would drop a second
+    /// time, so only `Copy` elements can be sorted through an initialized slice. Use
+    /// [`Scratch::from_uninit`] for any other elements:
+    ///
+    /// ```compile_fail
+    /// let mut buf = vec![String::new(); 8];
+    /// let scratch = aerosort::Scratch::from_slice(&mut buf);
+    /// ```
+    pub fn from_slice(buf: &'a mut [T]) -> Self where T: Copy {
+        Self { storage: Storage::Borrowed(buf) }
+    }
+
+    /// Create a scratch buffer over the uninitialized memory `buf`. Every merge into a scratch
+    /// buffer writes to a slot before reading from it, so `buf` never needs to be initialized.
+    ///
+    /// ```
+    /// use core::mem::MaybeUninit;
+    ///
+    /// let mut buf = [const { MaybeUninit::<String>::uninit() }; 16];
+    /// let mut v = ["b", "c", "a"].map(String::from);
+    /// aerosort::sort_with(&mut v, aerosort::Scratch::from_uninit(&mut buf));
+    /// assert_eq!(v, ["a", "b", "c"]);
+    /// ```
+    ///
+    /// Nothing is dropped through `buf`, so every element is dropped exactly once, by its owner:
+    ///
+    /// ```
+    /// use core::cell::Cell;
+    /// use core::mem::MaybeUninit;
+    ///
+    /// struct Tracked<'a>(u32, &'a Cell<usize>);
+    ///
+    /// impl Drop for Tracked<'_> {
+    ///     fn drop(&mut self) {
+    ///         self.1.set(self.1.get() + 1);
+    ///     }
+    /// }
+    ///
+    /// let drops = Cell::new(0);
+    /// let mut buf: Vec<MaybeUninit<Tracked>> = (0..50).map(|_| MaybeUninit::uninit()).collect();
+    /// let mut scratch = aerosort::Scratch::from_uninit(&mut buf);
+    /// for len in [100, 60] {
+    ///     let mut v: Vec<_> = (0..len).map(|i| Tracked(i * 37 % len, &drops)).collect();
+    ///     scratch.sort_by_key(&mut v, |x| x.0);
+    ///     assert!(v.iter().map(|x| x.0).eq(0..len));
+    /// }
+    ///
+    /// drop(buf);
+    /// assert_eq!(drops.get(), 160);
+    /// ```
+    pub fn from_uninit(buf: &'a mut [MaybeUninit<T>]) -> Self {
+        Self { storage: Storage::Spare(buf) }
+    }
+
+    /// Create a scratch buffer over the capacity of `buf`, clearing it first.
+    ///
+    /// ```
+    /// let mut buf = heapless::Vec::<u32, 64>::new();
+    /// let mut v = [5, 3, 8, 1, 9, 2];
+    /// aerosort::sort_with(&mut v, aerosort::Scratch::from_heapless(&mut buf));
+    /// assert_eq!(v, [1, 2, 3, 5, 8, 9]);
+    /// ```
+    #[cfg(feature = "heapless")]
+    pub fn from_heapless<const N: usize>(buf: &'a mut heapless::Vec<T, N>) -> Self {
+        buf.clear();
+        Self::from_uninit(unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr().cast(), N) })
+    }
+
+    /// Create an empty scratch buffer that allocates as needed.
+    #[cfg(feature = "alloc")]
+    pub fn new() -> Self {
+        Self { storage: Storage::Owned(Vec::new()) }
+    }
+
+    /// Create a scratch buffer that owns space for `len` elements.
+    ///
+    /// ```
+    /// let mut v: Vec<u64> = (0..1000).rev().collect();
+    /// aerosort::sort_with(&mut v, aerosort::Scratch::with_len(500));
+    /// assert!(v.iter().copied().eq(0..1000));
+    /// ```
+    #[cfg(feature = "alloc")]
+    pub fn with_len(len: usize) -> Self {
+        Self { storage: Storage::Owned(Vec::with_capacity(len)) }
+    }
+
+    /// Sort `v`.
+    pub fn sort(&mut self, v: &mut [T]) where T: Ord {
+        self.sort_by(v, T::cmp)
+    }
+
+    /// Sort `v` with a comparison function `cmp`.
+    pub fn sort_by(&mut self, v: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering) {
+        self.reserve(v.len());
+        crate::sort_general(v, slots(self), &mut |x, y| cmp(x, y) == Ordering::Less)
+    }
+
+    /// Sort `v` with a mapping `f` from elements to keys.
+    pub fn sort_by_key<K: Ord>(&mut self, v: &mut [T], mut f: impl FnMut(&T) -> K) {
+        self.reserve(v.len());
+        crate::sort_general(v, slots(self), &mut |x, y| f(x).lt(&f(y)))
+    }
+
+    // Grow an owned allocation so that it can perform every merge of a slice of length `n`.
+    fn reserve(&mut self, _n: usize) {
+        #[cfg(feature = "alloc")]
+        if let Storage::Owned(buf) = &mut self.storage {
+            buf.reserve(_n / 2);
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> Default for Scratch<'_, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An external buffer for [`crate::sort_with`] and the other `_with` functions: a mutable slice or
+/// array of `Copy` elements, a mutable slice of uninitialized elements, or a [`Scratch`] buffer.
+///
+/// Merges leave bitwise copies of elements behind in the buffer, so a buffer that drops its
+/// elements would drop them a second time. A slice of other elements is therefore rejected; pass
+/// uninitialized memory instead:
+///
+/// ```compile_fail
+/// let mut v = ["b", "a"].map(String::from);
+/// aerosort::sort_with(&mut v, &mut vec![String::new(); 2][..]);
+/// ```
+///
+/// ```
+/// use core::mem::MaybeUninit;
+///
+/// let mut v = ["b", "a"].map(String::from);
+/// aerosort::sort_with(&mut v, &mut [const { MaybeUninit::uninit() }; 2][..]);
+/// assert_eq!(v, ["a", "b"]);
+/// ```
+///
+/// The same holds for every function that takes a buffer:
+///
+/// ```compile_fail
+/// let mut v = ["b", "a"].map(String::from);
+/// aerosort::stable_partition_with(&mut v, &mut vec![String::new(); 2][..], |x| x == "a");
+/// ```
+///
+/// ```compile_fail
+/// let mut v = ["b", "a"].map(String::from);
+/// aerosort::sort_with_by_u8_key(&mut v, &mut vec![String::new(); 2][..], |x| x.as_bytes()[0]);
+/// ```
+///
+/// ```compile_fail
+/// let mut v = ["b", "a"].map(String::from);
+/// aerosort::merge_runs_with(&mut v, &mut vec![String::new(); 2][..], &[1]);
+/// ```
+///
+/// ```compile_fail
+/// let mut v = ["b", "a"].map(String::from);
+/// aerosort::sort_with_by_bytes(&mut v, &mut vec![String::new(); 2][..], |x| x.as_bytes());
+/// ```
+///
+/// ```compile_fail
+/// let mut v = ["b", "a"].map(String::from);
+/// aerosort::SortBuilder::new(&mut v).with_buffer(&mut vec![String::new(); 2][..]).run();
+/// ```
+pub trait Buffer<T>: sealed::Sealed<T> {}
+
+impl<T, B: sealed::Sealed<T>> Buffer<T> for B {}
+
+pub(crate) mod sealed {
+    pub trait Sealed<T> {
+        // Return the slots of the buffer, which may be uninitialized, and which must never be
+        // read before they are written or dropped through the result
+        unsafe fn slots(&mut self) -> &mut [T];
+    }
+}
+
+/// Return the slots of `buf` as a slice for merges to write to before they read.
+pub(crate) fn slots<T>(buf: &mut impl Buffer<T>) -> &mut [T] {
+    unsafe { buf.slots() }
+}
+
+impl<T: Copy> sealed::Sealed<T> for &mut [T] {
+    unsafe fn slots(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+impl<T: Copy, const N: usize> sealed::Sealed<T> for &mut [T; N] {
+    unsafe fn slots(&mut self) -> &mut [T] {
+        &mut self[..]
+    }
+}
+
+impl<T> sealed::Sealed<T> for [T; 0] {
+    unsafe fn slots(&mut self) -> &mut [T] {
+        &mut []
+    }
+}
+
+impl<T> sealed::Sealed<T> for Empty<T> {
+    unsafe fn slots(&mut self) -> &mut [T] {
+        &mut []
+    }
+}
+
+impl<T> sealed::Sealed<T> for &mut [MaybeUninit<T>] {
+    unsafe fn slots(&mut self) -> &mut [T] {
+        core::slice::from_raw_parts_mut(self.as_mut_ptr().cast(), self.len())
+    }
+}
+
+impl<T> sealed::Sealed<T> for Scratch<'_, T> {
+    unsafe fn slots(&mut self) -> &mut [T] {
+        match &mut self.storage {
+            Storage::Borrowed(buf) => buf,
+            Storage::Spare(buf) => buf.slots(),
+
+            #[cfg(feature = "alloc")]
+            Storage::Owned(buf) => {
+                core::slice::from_raw_parts_mut(buf.as_mut_ptr(), buf.capacity())
+            }
+        }
+    }
+}
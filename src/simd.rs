@@ -0,0 +1,159 @@
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::*;
+
+/// How the values of a 32-bit primitive type map to `i32` keys with the same order. Every mapping
+/// is its own inverse.
+#[derive(Clone, Copy)]
+pub enum Lane {
+    I32,
+    U32,
+    F32,
+}
+
+impl Lane {
+    fn key(self, x: i32) -> i32 {
+        match self {
+            Lane::I32 => x,
+            Lane::U32 => x ^ i32::MIN,
+            // Flip the magnitude bits of negative values, as `f32::total_cmp` does
+            Lane::F32 => x ^ (((x >> 31) as u32) >> 1) as i32,
+        }
+    }
+}
+
+/// Sort `v`, whose values are laid out as `lane` describes, merging runs with AVX2 if the CPU
+/// supports it. Return `false` without touching `v` otherwise.
+///
+/// Values that map to equal keys are identical, so the order of equal values cannot be observed.
+///
+/// Cost: `O(n log n)` comparisons and `O(n log n)` moves, and `n / 2` elements of heap allocation.
+pub fn sort_lanes<T: Copy>(v: &mut [T], lane: Lane) -> bool {
+    #[cfg(target_arch = "x86_64")]
+    if std::is_x86_feature_detected!("avx2") {
+        debug_assert_eq!(core::mem::size_of::<T>(), 4);
+        let v = unsafe { core::slice::from_raw_parts_mut(v.as_mut_ptr().cast::<i32>(), v.len()) };
+
+        v.iter_mut().for_each(|x| *x = lane.key(*x));
+        sort_keys(v);
+        v.iter_mut().for_each(|x| *x = lane.key(*x));
+        return true;
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = (v, lane);
+    false
+}
+
+// Sort `v` as [`crate::aero::sort_full`] does with a buffer of `n / 2` elements, merging with
+// `merge_up_avx2` whenever the left run is the shorter one, assuming the CPU supports AVX2.
+#[cfg(target_arch = "x86_64")]
+fn sort_keys(v: &mut [i32]) {
+    use crate::merge::Merge;
+
+    let mut ext = alloc::vec![0; v.len() / 2];
+    let base = &mut crate::mini::NetworkSort;
+    crate::aero::sort_merging(v, base, &mut |x, y| x < y, |[a, b], less| {
+        let (n, m) = (a.len(), b.len());
+        if n <= m {
+            ext[..n].copy_from_slice(a);
+            unsafe { merge_up_avx2(ext.as_ptr(), n, a.as_mut_ptr(), m) }
+        } else {
+            ext.merge([a, b], less);
+        }
+    });
+}
+
+/// Merge `a`, the `n` elements at `buf`, and `b`, the `m` elements at `dst + n`, starting at `dst`
+/// and building the result rightwards, as [`crate::merge::merge_up`] does. While both runs have 8
+/// elements left, we take 8 at a time from the run with the smaller next element and merge them
+/// with the 8 largest elements taken so far through a bitonic merge network; the rest is merged
+/// with scalar code.
+///
+/// Cost: `O(n + m)` comparisons and `O(n + m)` moves.
+///
+/// # Safety
+///
+/// The CPU must support AVX2, `buf` must be valid for `n` reads and `dst` for `n + m` reads and
+/// writes, and the two must not overlap.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn merge_up_avx2(buf: *const i32, n: usize, dst: *mut i32, m: usize) {
+    let b = dst.add(n);
+    let [mut i, mut j, mut out] = [0, 0, 0];
+
+    // The 8 largest elements taken so far, in order, of which those from `held` on are not written
+    let mut carry = [0; 8];
+    let mut held = 8;
+
+    if n >= 8 && m >= 8 {
+        let mut hi = _mm256_loadu_si256(b.cast());
+        j = 8;
+
+        // Every element written is at most every element taken after it, so we can write `lo`
+        loop {
+            let take_a = j == m || (i < n && *buf.add(i) < *b.add(j));
+            let next = if take_a && i + 8 <= n {
+                i += 8;
+                buf.add(i - 8)
+            } else if !take_a && j + 8 <= m {
+                j += 8;
+                b.add(j - 8)
+            } else {
+                break;
+            };
+
+            let lo;
+            (lo, hi) = merge_8(hi, _mm256_loadu_si256(next.cast()));
+            _mm256_storeu_si256(dst.add(out).cast(), lo);
+            out += 8;
+        }
+
+        _mm256_storeu_si256(carry.as_mut_ptr().cast(), hi);
+        held = 0;
+    }
+
+    // Three-way merge of the rest of `carry`, `a`, and `b`. Writes never overtake the rest of `b`,
+    // which is already in place once the others run out.
+    loop {
+        let from_carry = held < 8 && (i == n || carry[held] <= *buf.add(i));
+        if !from_carry && i == n {
+            break;
+        }
+
+        let x = if from_carry { carry[held] } else { *buf.add(i) };
+        if j < m && *b.add(j) < x {
+            *dst.add(out) = *b.add(j);
+            j += 1;
+        } else {
+            *dst.add(out) = x;
+            if from_carry { held += 1 } else { i += 1 }
+        }
+        out += 1;
+    }
+}
+
+// Merge the sorted vectors `a` and `b` into the sorted vectors of their 8 smallest and 8 largest
+// elements.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn merge_8(a: __m256i, b: __m256i) -> (__m256i, __m256i) {
+    // Against `a`, the reversal of `b` makes a bitonic sequence, which one layer of comparators
+    // splits into two bitonic halves
+    let b = _mm256_permutevar8x32_epi32(b, _mm256_setr_epi32(7, 6, 5, 4, 3, 2, 1, 0));
+    let [lo, hi] = [_mm256_min_epi32(a, b), _mm256_max_epi32(a, b)];
+    (sort_bitonic_8(lo), sort_bitonic_8(hi))
+}
+
+// Sort the bitonic vector `x` with comparators between lanes 4, 2, and 1 apart.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn sort_bitonic_8(x: __m256i) -> __m256i {
+    let t = _mm256_permute2x128_si256::<0x01>(x, x);
+    let x = _mm256_blend_epi32::<0b1111_0000>(_mm256_min_epi32(x, t), _mm256_max_epi32(x, t));
+    let t = _mm256_shuffle_epi32::<0b01_00_11_10>(x);
+    let x = _mm256_blend_epi32::<0b1100_1100>(_mm256_min_epi32(x, t), _mm256_max_epi32(x, t));
+    let t = _mm256_shuffle_epi32::<0b10_11_00_01>(x);
+    _mm256_blend_epi32::<0b1010_1010>(_mm256_min_epi32(x, t), _mm256_max_epi32(x, t))
+}
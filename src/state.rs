@@ -1,30 +1,43 @@
 use crate::keys::Keys;
+use crate::merge::{gallop_left, gallop_right, partition};
+use crate::metrics;
+use crate::trace::trace;
 
-use sort_util::op::{self, lower_bound, search_unique};
+use sort_util::op::{self, lower_bound};
 use sort_util::{op::Found, GenerateSlice, RawMut};
 
 /// A state storing properties of a current key collection originating leftwards.
 pub struct LeftCollectState<T> {
     location: *mut T,
     keys: usize,
+
+    // Whether the last key was inserted into the upper half of the collection
+    upper: bool,
 }
 
 impl<T> LeftCollectState<T> {
     /// Initialize a new key collection at `location` of length `keys`, assuming it is pre-sorted.
     pub fn new(location: *mut T, keys: usize) -> Self {
-        Self { location, keys }
+        Self { location, keys, upper: true }
     }
 
     unsafe fn insert<F: FnMut(&T, &T) -> bool>(&mut self, key: *mut T, less: &mut F) {
-        let Found(false, index) = search_unique(self.location, self.keys, key, less) else {
+        let found = search_keys(self.location, self.keys, key, self.upper, less);
+        let Found(false, index) = found else {
             return;
         };
+        self.upper = 2 * index >= self.keys;
 
-        let shift = key.offset_from(self.location) as usize - self.keys;
+        // `key` lies past the collection in the slice being scanned, which spans at most
+        // `isize::MAX` bytes, so the offset is exact and at least `keys`
+        let offset = key.offset_from(self.location);
+        debug_assert!(offset >= self.keys as isize, "key {offset} lies within the collection");
+        let shift = offset as usize - self.keys;
         op::rotate(self.location, self.keys + shift, self.keys);
         self.location = self.location.add(shift);
 
         op::insert_left(key, self.keys - index);
+        metrics::moved(self.keys + shift + self.keys - index + 1);
         self.keys += 1;
     }
 
@@ -55,23 +68,143 @@ impl<T> LeftCollectState<T> {
         let (s, n) = v.raw_mut();
         unsafe {
             let shift = self.location.offset_from(s) as usize;
+            debug_assert!(shift + self.keys <= n, "collection lies outside the slice");
 
             // Move our collection to the left of `v` and rotate the interior to be sorted
             op::rotate(s, shift + self.keys, shift);
+            metrics::moved(shift + self.keys);
 
             let (internal_buffer, task) = s.crop(0..n).split_at_mut(self.keys);
             UnionState {
                 align: KeysAlignment::Left,
                 keys: Keys::new(internal_buffer, buffer_len),
                 task,
+                complete: false,
+            }
+        }
+    }
+}
+
+/// A state storing properties of a current key collection originating rightwards.
+///
+/// A sort collects its keys this way when distinct values are denser at the right end of a slice,
+/// and merges them back in from the right once done:
+///
+/// ```
+/// // Only the last `sqrt(2n)` elements are distinct; the rest are long runs of 2 values
+/// let n: usize = 10_000;
+/// let k = (2 * n).isqrt();
+/// let key = |i: usize| if i < n - k { i / 1000 % 2 } else { n - i };
+/// let input: Vec<(usize, usize)> = (0..n).map(|i| (key(i), i)).collect();
+///
+/// let mut v = input.clone();
+/// aerosort::sort_by_key(&mut v, |x| x.0);
+/// assert!(v.windows(2).all(|w| w[0] < w[1]));
+///
+/// # #[cfg(feature = "internal")] {
+/// // The keys are the last `k` elements, leaving the rest in place as the task
+/// let mut v = input.clone();
+/// let mut less = |x: &(usize, usize), y: &(usize, usize)| x.0 < y.0;
+/// let mut state = aerosort::state::collect_keys(&mut v, &mut less);
+/// assert_eq!(state.key_count(), k);
+/// assert_eq!(state.task, &input[..n - k]);
+///
+/// aerosort::sort_by_key(state.task, |x| x.0);
+/// state.restore_by(&mut less);
+/// assert!(v.windows(2).all(|w| w[0] < w[1]));
+/// # }
+/// ```
+pub struct RightCollectState<T> {
+    location: *mut T,
+    keys: usize,
+
+    // Whether the last key was inserted into the upper half of the collection
+    upper: bool,
+}
+
+impl<T> RightCollectState<T> {
+    /// Initialize a new key collection at `location` of length `keys`, assuming it is pre-sorted.
+    pub fn new(location: *mut T, keys: usize) -> Self {
+        Self { location, keys, upper: true }
+    }
+
+    unsafe fn insert<F: FnMut(&T, &T) -> bool>(&mut self, key: *mut T, less: &mut F) {
+        let found = search_keys(self.location, self.keys, key, self.upper, less);
+        let Found(false, index) = found else {
+            return;
+        };
+        self.upper = 2 * index >= self.keys;
+
+        // `key` lies before the collection in the slice being scanned, as above
+        let offset = self.location.offset_from(key);
+        debug_assert!(offset >= 1, "key {offset} lies within the collection");
+        let shift = offset as usize - 1;
+        op::rotate(key.add(1), shift + self.keys, shift);
+
+        op::rotate(key, index + 1, 1);
+        metrics::moved(shift + self.keys + index + 1);
+        self.location = key;
+        self.keys += 1;
+    }
+
+    /// Perform a complete key collection of `v`, scanning to the left. Abort collection once
+    /// `limit` keys are collected. Return the number of keys collected.
+    pub fn scan<F: FnMut(&T, &T) -> bool>(
+        &mut self, v: &mut [T], limit: usize, less: &mut F,
+    ) -> usize {
+        let s = v.as_mut_ptr();
+        let initial_keys = self.keys;
+
+        for i in (0..v.len()).rev() {
+            // Try to insert the current key
+            unsafe { self.insert(s.add(i), less); }
+
+            // Break early if we reach the desired number of keys
+            if self.keys == limit {
+                break;
+            }
+        }
+
+        self.keys - initial_keys
+    }
+
+    /// Move the key collection to the right of `v`, ensuring it is sorted ascending. Return a union
+    /// state with keys that have an internal buffer of length `buffer_len`.
+    pub fn into_union_state<'a>(self, v: &mut [T], buffer_len: usize) -> UnionState<'a, T> {
+        let (s, n) = v.raw_mut();
+        unsafe {
+            let offset = self.location.offset_from(s) as usize;
+            debug_assert!(offset + self.keys <= n, "collection lies outside the slice");
+            let shift = n - self.keys - offset;
+
+            // Move our collection to the right of `v` and rotate the interior to be sorted
+            op::rotate(self.location, self.keys + shift, self.keys);
+            metrics::moved(self.keys + shift);
+
+            let (task, internal_buffer) = s.crop(0..n).split_at_mut(n - self.keys);
+            UnionState {
+                align: KeysAlignment::Right,
+                keys: Keys::new(internal_buffer, buffer_len),
+                task,
+                complete: false,
             }
         }
     }
 }
 
+// Search the `n` sorted keys at `s` for `key` as `search_unique` does, galloping from the end of
+// the half given by `upper`. Keys scanned from a run land next to the previous key, so a run of
+// distinct keys costs `O(1)` comparisons per key instead of `O(log n)`.
+unsafe fn search_keys<T, F: FnMut(&T, &T) -> bool>(
+    s: *mut T, n: usize, key: *mut T, upper: bool, less: &mut F,
+) -> Found {
+    let pred = |i: usize| less(&*s.add(i), &*key);
+    let i = if upper { gallop_left(n, pred) } else { gallop_right(n, pred) };
+    Found(i != n && !less(&*key, &*s.add(i)), i)
+}
+
 enum KeysAlignment {
     Left,
-    #[allow(unused)]
     Right,
 }
 
@@ -84,11 +217,41 @@ pub struct UnionState<'a, T> {
 
     /// The slice to be sorted.
     pub task: &'a mut [T],
+
+    /// Whether every distinct value in the slice was collected as a key.
+    pub complete: bool,
 }
 
 impl<'a, T> UnionState<'a, T> {
+    /// Return the number of keys collected, which is a lower bound on the number of distinct
+    /// values in the slice (exact if [`UnionState::complete`] holds).
+    pub fn key_count(&self) -> usize {
+        self.keys.inner.len()
+    }
+
     /// Restore all keys into the slice, completing the sorting operation.
     ///
+    /// Only the internal buffer needs sorting, as the tags stay sorted and below it. The keys are
+    /// then merged in with rotations, which binary search for where each one goes: with `sqrt n`
+    /// keys, this takes far fewer comparisons than the `n` a merge through a buffer would, and
+    /// still `O(n)` moves.
+    ///
+    /// ```
+    /// # #[cfg(feature = "internal")] {
+    /// let mut v: Vec<u64> = (0..10_000).map(|i| i * 7919 % 10_007).collect();
+    /// let comparisons = core::cell::Cell::new(0);
+    /// let mut less = |x: &u64, y: &u64| { comparisons.set(comparisons.get() + 1); x < y };
+    ///
+    /// let mut state = aerosort::state::collect_keys(&mut v, &mut less);
+    /// aerosort::sort_by(state.task, u64::cmp);
+    /// comparisons.set(0);
+    /// state.restore_by(&mut less);
+    ///
+    /// assert!(v.windows(2).all(|w| w[0] < w[1]));
+    /// assert!(comparisons.get() < 4000, "{comparisons:?}");
+    /// # }
+    /// ```
+    ///
     /// Cost: `O(sqrt n * log n)` comparisons and `O(n)` moves.
     pub fn restore_by<F: FnMut(&T, &T) -> bool>(&mut self, less: &mut F) {
         use crate::merge::{merge_left, merge_right};
@@ -99,27 +262,200 @@ impl<'a, T> UnionState<'a, T> {
             KeysAlignment::Right => merge_left([self.task, self.keys.inner], less)
         }
     }
+
+    /// Sort `task` with our keys and `ext` as an external buffer, as sorting [`UnionState::task`]
+    /// would, leaving the keys in place to sort another slice. This skips collecting keys again
+    /// when sorting many slices of the same length. Call [`UnionState::restore_by`] once done to
+    /// restore the keys into [`UnionState::task`], which must then be sorted in turn.
+    ///
+    /// Cost: `O(n log n)` comparisons and `O(n log n)` moves, given more than 12 keys; with fewer,
+    /// merges may fall back to rotations and cost more.
+    ///
+    /// # Safety
+    ///
+    /// The keys must still be as key collection left them, so neither [`UnionState::restore_by`]
+    /// nor [`UnionState::distribute_by`] may have been called on this state, and `less` must order
+    /// the keys as it did when they were collected.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `task` and [`UnionState::task`] differ in length.
+    #[cfg(feature = "internal")]
+    pub unsafe fn sort_task<F: FnMut(&T, &T) -> bool>(
+        &mut self, task: &mut [T], ext: &mut [T], less: &mut F,
+    ) {
+        use crate::aero::{sort_with_strategy, SortStrategy};
+
+        assert_eq!(task.len(), self.task.len(), "task length differs from the collected task");
+        sort_with_strategy(task, ext, SortStrategy::Keys(&mut self.keys), less);
+    }
+
+    /// Stably distribute the task among the keys and restore them, completing the sorting
+    /// operation. Each split partitions pieces that fit in `ext` directly and joins them with
+    /// rotations. This assumes every distinct value in the slice was collected as a key.
+    ///
+    /// Cost: `O(n log k)` comparisons and `O(n log (n/b + 1) log k)` moves for `b = ext.len()`,
+    /// which is `O(n)` moves when `b` is a fixed fraction of `n`.
+    pub fn distribute_by<F: FnMut(&T, &T) -> bool>(&mut self, ext: &mut [T], less: &mut F) {
+        fn distribute<T, F: FnMut(&T, &T) -> bool>(
+            task: &mut [T], keys: &[T], ext: &mut [T], less: &mut F,
+        ) {
+            if keys.len() <= 1 {
+                return;
+            }
+
+            // Split the task around the middle key and distribute each side among its own keys
+            let mid = keys.len() / 2;
+            let split = partition(task, ext, &mut |x| less(x, &keys[mid]));
+            let (left, right) = task.split_at_mut(split);
+            distribute(left, &keys[..mid], ext, less);
+            distribute(right, &keys[mid..], ext, less);
+        }
+
+        // Our keys are still sorted, since we have not merged anything yet
+        distribute(self.task, self.keys.inner, ext, less);
+        self.restore_by(less);
+    }
 }
 
 /// Collect keys from `v` and return a [`UnionState`] representing the created state.
+///
+/// Each key is placed by galloping from the end of the collection that the previous key went to,
+/// so distinct values that arrive in order cost few comparisons each:
+///
+/// ```
+/// let mut v: Vec<u32> = (0..1 << 16).map(|i| i / 4).collect();
+/// let mut comparisons = 0;
+/// let state = aerosort::state::collect_keys(&mut v, &mut |x: &u32, y: &u32| {
+///     comparisons += 1;
+///     x < y
+/// });
+///
+/// assert_eq!(state.key_count(), 362);
+/// assert!(comparisons < 6500);
+/// ```
+///
+/// With the most keys a sort asks for split between both ends of a long slice, the collection
+/// gathers one half, then travels the length of the slice to gather the other:
+///
+/// ```
+/// let n = 1 << 20;
+/// let k = (2 * n as u32).isqrt();
+/// let mut v = vec![0; n];
+/// v[..k as usize / 2].iter_mut().zip(0..).for_each(|(x, i)| *x = i);
+/// v[n - k as usize / 2..].iter_mut().zip(k / 2..).for_each(|(x, i)| *x = i);
+///
+/// let state = aerosort::state::collect_keys(&mut v, &mut |x: &u32, y: &u32| x < y);
+/// assert_eq!(state.key_count(), k as usize);
+/// drop(state);
+/// assert!(v[..k as usize].iter().copied().eq(0..k));
+/// assert!(v[k as usize..].iter().all(|&x| x == 0));
+/// ```
+#[cfg(feature = "internal")]
 pub fn collect_keys<'a, T, F: FnMut(&T, &T) -> bool>(
     v: &'a mut [T], less: &mut F,
 ) -> UnionState<'a, T> {
-    let n = v.len();
-
     // Collecting `2 sqrt n` keys reduces total comparisons by ~1% with large `n`, but results in a
     // more expensive final redistribution, so we might as well not worry about that.
-    let mut k = lower_bound::binary(n, |i| i * i < 2 * n);
-    k -= (k * k != 2 * n) as usize;    // `keys == (2 * n).isqrt()`
+    collect_keys_with_factor(v, 2, less)
+}
+
+/// The result of analyzing a slice with [`analyze`].
+#[cfg(feature = "internal")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SortAnalysis {
+    /// A lower bound on the number of distinct values in the slice, which is exact unless it
+    /// reaches the number of keys a sort asks for (`floor(sqrt 2n)`).
+    pub distinct_lower_bound: usize,
+
+    /// The length of the shortest external buffer that sorting the slice makes full use of: one
+    /// that lets a sort distribute the slice among at most 12 keys, or otherwise one with which it
+    /// needs no key collection. This is 0 if sorting never benefits from an external buffer.
+    pub recommended_buffer: usize,
+}
+
+/// Collect keys from `v` as a sort would, and report what they reveal about `v`. This leaves `v`
+/// holding all of its original elements in an unspecified order.
+///
+/// Cost: `O(n log n)` comparisons and `O(n)` moves.
+#[cfg(feature = "internal")]
+pub fn analyze<T, F: FnMut(&T, &T) -> bool>(v: &mut [T], less: &mut F) -> SortAnalysis {
+    let n = v.len();
+    if n < 2 {
+        return SortAnalysis { distinct_lower_bound: n, recommended_buffer: 0 };
+    }
 
-    // Collect up to `k` keys
-    let mut collection = LeftCollectState::new(v.as_mut_ptr(), 1);
-    collection.scan(&mut v[1..], k, less);
-    k = collection.keys;
+    let state = collect_keys(v, less);
+    let distinct_lower_bound = state.key_count();
 
+    // With few enough distinct values, a sort distributes the slice among its keys directly, which
+    // a buffer of `n / 32` elements makes take `O(n)` moves
+    let distributed = state.complete && distinct_lower_bound <= 12;
+    SortAnalysis { distinct_lower_bound, recommended_buffer: n / if distributed { 32 } else { 2 } }
+}
+
+/// Collect up to `(factor * n).isqrt()` keys from `v` and return a [`UnionState`] representing the
+/// created state, assuming `factor != 0`.
+pub fn collect_keys_with_factor<'a, T, F: FnMut(&T, &T) -> bool>(
+    v: &'a mut [T], factor: usize, less: &mut F,
+) -> UnionState<'a, T> {
+    let n = v.len();
+    let target = factor.saturating_mul(n);
+
+    // Squares of indices past `sqrt usize::MAX` overflow, which happens for `n > 2^17` on 32-bit
+    // targets; they exceed `target` anyway
+    let square = |i: usize| i.checked_mul(i);
+    let mut k = lower_bound::binary(n, |i| square(i).is_some_and(|sq| sq < target));
+    k -= (square(k) != Some(target)) as usize;    // `keys == target.isqrt()`
+    let limit = k;
+
+    // Probe both ends and collect from the side that looks more distinct
+    let probe = k.min(n);
+    let right = count_distinct_adjacent(&v[n - probe..], less)
+        > count_distinct_adjacent(&v[..probe], less);
+    let mut state = if right {
+        // Collect up to `k` keys
+        let mut collection = RightCollectState::new(unsafe { v.as_mut_ptr().add(n - 1) }, 1);
+        collection.scan(&mut v[..n - 1], k, less);
+        k = collection.keys;
+
+        // Move our collection to the far right
+        collection.into_union_state(v, buffer_len(n, k))
+    } else {
+        // Collect up to `k` keys
+        let mut collection = LeftCollectState::new(v.as_mut_ptr(), 1);
+        collection.scan(&mut v[1..], k, less);
+        k = collection.keys;
+
+        // Move our collection to the far left
+        collection.into_union_state(v, buffer_len(n, k))
+    };
+
+    // If we collected fewer keys than we asked for, we must have found every distinct value
+    state.complete = state.key_count() < limit;
+    trace!(
+        "collect_keys: {} keys={k} complete={}",
+        if right { "right" } else { "left" },
+        state.complete,
+    );
+    state
+}
+
+// Return the internal buffer length to use for `k` keys collected from `n` elements. This is the
+// largest buffer that still lets every merge of the sort use a scrolling block merge, if any does.
+//
+// With `t` tags, the buffer holds `k - t` elements, and a scrolling block merge is possible when
+// its left run is shorter than `(t + 1) * (k - t)` (see `Keys::can_scrolling_block_merge`). The
+// longest left run of the sort is `m = (n - k) / 2`, so we want the least `t <= k / 2` with
+// `(t + 1) * (k - t) > m`. For integers, `t < m / (k - t)` (rounding down) holds exactly when
+// `(t + 1) * (k - t) <= m`, and the product increases with `t` on that range, so the binary search
+// below finds it exactly.
+fn buffer_len(n: usize, k: usize) -> usize {
     // We can expand our buffer as long as we have enough keys
-    let buffer_len = k - lower_bound::binary(k / 2, |len| len < (n - k) / 2 / (k - len));
+    k - lower_bound::binary(k / 2, |len| len < (n - k) / 2 / (k - len))
+}
 
-    // Move our collection to the far left
-    collection.into_union_state(v, buffer_len)
+// Return the number of adjacent pairs in `v` that are not equal.
+fn count_distinct_adjacent<T, F: FnMut(&T, &T) -> bool>(v: &[T], less: &mut F) -> usize {
+    v.windows(2).filter(|w| less(&w[0], &w[1]) || less(&w[1], &w[0])).count()
 }
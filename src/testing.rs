@@ -0,0 +1,80 @@
+//! Generators of inputs that drive a sort down each of its paths, for testing code that sorts with
+//! aerosort. Every generator fills a slice of `u64` and is deterministic given its seed, with no
+//! dependency on an outside random number generator:
+//!
+//! ```
+//! use aerosort::testing;
+//!
+//! let [mut a, mut b] = [[0; 1000]; 2];
+//! testing::few_distinct(&mut a, 20, 7);
+//! testing::few_distinct(&mut b, 20, 7);
+//! assert_eq!(a, b);
+//!
+//! for generate in [testing::costly_key_collection, testing::rotation_merges] {
+//!     generate(&mut a, 7);
+//!     aerosort::sort(&mut a);
+//!     assert!(a.windows(2).all(|w| w[0] <= w[1]));
+//! }
+//! ```
+
+// The SplitMix64 generator (Steele, Lea, and Flood, 2014), which passes BigCrush and needs a
+// single word of state.
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let z = (self.0 ^ (self.0 >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        let z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Return a value in `0..bound`, assuming `bound != 0`.
+    fn below(&mut self, bound: u64) -> u64 {
+        ((self.next() as u128 * bound as u128) >> 64) as u64
+    }
+}
+
+/// Fill `v` with uniformly random values.
+pub fn random(v: &mut [u64], seed: u64) {
+    let mut rng = SplitMix(seed);
+    v.iter_mut().for_each(|x| *x = rng.next());
+}
+
+/// Fill `v` with random values from `0..distinct`, or with zeros if `distinct == 0`. With at most
+/// 12 distinct values, a sort distributes elements among its keys directly, by rotations unless
+/// given an external buffer of `n / 32` elements; with up to `sqrt(2n)`, it collects every distinct
+/// value as a key, too few for its longest merges to scroll their buffer.
+pub fn few_distinct(v: &mut [u64], distinct: u64, seed: u64) {
+    let mut rng = SplitMix(seed);
+    v.iter_mut().for_each(|x| *x = if distinct == 0 { 0 } else { rng.below(distinct) });
+}
+
+/// Fill `v` with values that make collecting keys as costly as it gets: one fewer distinct value
+/// than a sort asks for, in random order, so that collection scans the whole slice and spends
+/// `O(log n)` comparisons on every element.
+pub fn costly_key_collection(v: &mut [u64], seed: u64) {
+    let target = (2 * v.len() as u64).isqrt();
+    few_distinct(v, target.saturating_sub(1), seed);
+}
+
+/// Fill `v` with values that make the merges of a sort without an external buffer fall back to
+/// rotation block merges, which the sort uses when it collects too few keys for a scrolling block
+/// merge: 16 distinct values in random order. This holds for slices of at least 200 elements.
+pub fn rotation_merges(v: &mut [u64], seed: u64) {
+    few_distinct(v, 16, seed);
+}
+
+/// Fill `v` with ascending runs of `run_len` values each (the last one may be shorter), each
+/// starting from a random value below `2^32`, or with random values if `run_len == 0`.
+pub fn sawtooth(v: &mut [u64], run_len: usize, seed: u64) {
+    if run_len == 0 {
+        return random(v, seed);
+    }
+
+    let mut rng = SplitMix(seed);
+    for run in v.chunks_mut(run_len) {
+        let start = rng.below(1 << 32);
+        run.iter_mut().zip(start..).for_each(|(x, value)| *x = value);
+    }
+}
@@ -0,0 +1,154 @@
+//! A sink for the decisions a sort makes, for debugging. Without the `trace` feature, the `trace!`
+//! macro expands to nothing.
+
+#[cfg(feature = "trace")]
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering::{Acquire, Relaxed, Release}};
+
+// The installed sink, which points to a `&mut (dyn FnMut(&str) + Send)` on the stack of
+// `trace_sorts`
+#[cfg(feature = "trace")]
+static SINK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+// Whether some thread holds the sink, which only one thread may touch at a time
+#[cfg(feature = "trace")]
+static BUSY: AtomicBool = AtomicBool::new(false);
+
+/// Pass a line describing a decision to the installed sink, if there is one.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "trace")]
+        crate::trace::emit(format_args!($($arg)*));
+    };
+}
+
+pub(crate) use trace;
+
+/// Call `f`, passing `sink` a line for every decision made by sorts running meanwhile: the
+/// strategy [`sort`](crate::sort) picks (`sort_full: ...`), the keys it collects
+/// (`collect_keys: ...`), the kind of every block merge with its counts of A and B blocks and the
+/// sizes of their undersized blocks (`block_merge: ...`), and where
+/// [`sort_hint_nearly_sorted`](crate::sort_hint_nearly_sorted) gives up on insertion
+/// (`sort_nearly_sorted: ...`). Return the result of `f`.
+///
+/// The sink is global rather than per-thread, so sorts on other threads are traced too, such as
+/// the workers of [`par_sort`](crate::par_sort); `sink` must then be `Send`. A line is dropped if
+/// the sink is busy with another, which includes lines from sorts that `sink` runs.
+///
+/// ```
+/// let mut lines = Vec::new();
+/// aerosort::trace_sorts(|line| lines.push(line.to_string()), || {
+///     let mut v: Vec<u32> = (0..1000).rev().collect();
+///     aerosort::sort(&mut v);
+///     aerosort::sort_with(&mut v, &mut [0; 500][..]);
+///     aerosort::sort_with(&mut [1, 0, 1, 0].repeat(100), &mut [0; 16][..]);
+/// });
+///
+/// assert_eq!(lines[0], "collect_keys: left keys=44 complete=false");
+/// assert_eq!(lines[1], "sort_full: keys n=1000 keys=44");
+/// assert!(lines[2].starts_with("block_merge: scrolling na="));
+/// assert!(lines.contains(&"sort_full: easy n=1000".to_string()));
+/// assert!(lines.contains(&"sort_full: distribute n=400 keys=2".to_string()));
+/// ```
+///
+/// A sink that is not `Send` is rejected, as it may be called from another thread:
+///
+/// ```compile_fail
+/// let count = std::rc::Rc::new(std::cell::Cell::new(0));
+/// aerosort::trace_sorts(|_| count.set(count.get() + 1), || aerosort::sort(&mut [2, 1]));
+/// ```
+///
+/// # Panics
+///
+/// Panics if sorts are already being traced, which includes calling this from within `sink`:
+///
+/// ```should_panic
+/// aerosort::trace_sorts(|_| aerosort::trace_sorts(|_| {}, || {}), || {
+///     aerosort::sort(&mut [1, 0].repeat(100));
+/// });
+/// ```
+#[cfg(feature = "trace")]
+pub fn trace_sorts<R>(mut sink: impl FnMut(&str) + Send, f: impl FnOnce() -> R) -> R {
+    let mut sink: &mut (dyn FnMut(&str) + Send) = &mut sink;
+    let installed = (&mut sink as *mut &mut (dyn FnMut(&str) + Send)).cast::<()>();
+
+    // A sink is installed while it runs, so this also keeps `sink` from waiting on itself below
+    assert!(SINK.load(Relaxed).is_null(), "sorts are already being traced");
+
+    let null = core::ptr::null_mut();
+    let vacant = with_sink(|| SINK.compare_exchange(null, installed, Relaxed, Relaxed).is_ok());
+    assert!(vacant, "sorts are already being traced");
+
+    // Uninstall the sink once no thread holds it, even if `f` panics
+    struct Uninstall;
+    impl Drop for Uninstall {
+        fn drop(&mut self) {
+            with_sink(|| SINK.store(core::ptr::null_mut(), Relaxed));
+        }
+    }
+
+    let _uninstall = Uninstall;
+    f()
+}
+
+// Wait until no other thread holds the sink, and call `f` while holding it.
+#[cfg(feature = "trace")]
+fn with_sink<R>(f: impl FnOnce() -> R) -> R {
+    while BUSY.compare_exchange_weak(false, true, Acquire, Relaxed).is_err() {
+        core::hint::spin_loop();
+    }
+
+    let _holding = Holding;
+    f()
+}
+
+// Let go of the sink when dropped.
+#[cfg(feature = "trace")]
+struct Holding;
+
+#[cfg(feature = "trace")]
+impl Drop for Holding {
+    fn drop(&mut self) {
+        BUSY.store(false, Release);
+    }
+}
+
+/// Format `args` and pass them to the installed sink, unless there is none or it is busy. Lines
+/// longer than 128 bytes are cut short.
+#[cfg(feature = "trace")]
+pub fn emit(args: core::fmt::Arguments) {
+    if BUSY.compare_exchange(false, true, Acquire, Relaxed).is_err() {
+        return;
+    }
+    let _holding = Holding;
+
+    let sink = SINK.load(Relaxed).cast::<&mut (dyn FnMut(&str) + Send)>();
+    if sink.is_null() {
+        return;
+    }
+
+    let mut line = Line { buf: [0; 128], len: 0 };
+    let _ = core::fmt::write(&mut line, args);
+    let line = unsafe { core::str::from_utf8_unchecked(&line.buf[..line.len]) };
+
+    // The sink outlives its installation, and holding `BUSY` makes this the only reference to it;
+    // it is `Send`, so calling it from whichever thread holds `BUSY` is sound
+    unsafe { (*sink)(line) }
+}
+
+// A line of at most 128 bytes, which drops whatever does not fit.
+#[cfg(feature = "trace")]
+struct Line {
+    buf: [u8; 128],
+    len: usize,
+}
+
+#[cfg(feature = "trace")]
+impl core::fmt::Write for Line {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let room = self.buf.len() - self.len;
+        let fit = (0..=room.min(s.len())).rev().find(|&i| s.is_char_boundary(i)).unwrap_or(0);
+        self.buf[self.len..self.len + fit].copy_from_slice(&s.as_bytes()[..fit]);
+        self.len += fit;
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! Sorts that check their own stability. [`sort_verified`] and its forms sort the original indices
+//! of the elements of `v` rather than the elements themselves, through the merges that
+//! [`crate::sort`] would use, then check with [`check_stable_by`] that equal elements kept their
+//! original order before moving the elements into place. Comparisons see only the elements, so the
+//! indices never affect the order. Every other sort in the crate is unaffected by this module.
+//!
+//! This takes `O(n)` indices of heap allocation for a slice of `n` elements. Slices of more than
+//! `u32::MAX` elements, and slices for which allocation fails, are sorted without the check.
+//!
+//! ```
+//! // Every strategy, from the base sort to rotation block merges, is checked
+//! for (n, distinct) in [(20, 3), (1000, 2), (1000, 16), (1000, 1000), (5000, 40)] {
+//!     let mut v: Vec<(u32, usize)> = (0..n).map(|i| ((i as u32 * 7919) % distinct, i)).collect();
+//!     aerosort::verify::sort_verified_by(&mut v, |x, y| x.0.cmp(&y.0));
+//!     assert!(v.windows(2).all(|w| w[0] < w[1]));
+//! }
+//! ```
+
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+use crate::mini::{BaseSort, Checked, InsertionSort};
+use crate::{metrics, SortConfig};
+
+/// Panic unless `order`, the original indices of the elements of `v` after a sort by `cmp`, lists
+/// every run of equal elements in ascending order. The message names the first two indices out of
+/// order.
+///
+/// ```should_panic
+/// // The equal elements at indices 0 and 2 were swapped
+/// let v = [1, 1, 2];
+/// aerosort::verify::check_stable_by(&v, &[2, 0, 1], |x: &i32, y| x.cmp(y));
+/// ```
+///
+/// # Panics
+///
+/// Panics if `order` is shorter than `v` or holds an index out of bounds of `v`, as well as on
+/// instability.
+pub fn check_stable_by<T>(v: &[T], order: &[u32], mut cmp: impl FnMut(&T, &T) -> Ordering) {
+    check(v, &order[..v.len()], &mut |x, y| cmp(x, y) == Ordering::Less);
+}
+
+// Panic if two adjacent indices of `order` point to equal elements of `v` in descending order.
+fn check<T, F: FnMut(&T, &T) -> bool>(v: &[T], order: &[u32], less: &mut F) {
+    for w in order.windows(2) {
+        let [i, j] = [w[0], w[1]];
+        if i > j && !less(&v[i as usize], &v[j as usize]) {
+            panic!("unstable sort: equal elements from indices {j} and {i} are out of order");
+        }
+    }
+}
+
+/// Sort `v` as [`crate::sort`] does, panicking if equal elements end up out of their original
+/// order.
+///
+/// Cost: that of [`crate::sort`], plus `n - 1` comparisons, `O(n)` moves, and `n` indices of heap
+/// allocation.
+///
+/// # Panics
+///
+/// Panics on instability, which the sorts of this crate never show.
+#[inline(always)]
+pub fn sort_verified<T: Ord>(v: &mut [T]) {
+    sort_verified_by(v, T::cmp)
+}
+
+/// Sort `v` with a comparison function `cmp` as [`sort_verified`] does. `cmp` must be a total
+/// order, or this may panic.
+#[inline(always)]
+pub fn sort_verified_by<T>(v: &mut [T], mut cmp: impl FnMut(&T, &T) -> Ordering) {
+    metrics::reset();
+
+    // Skip zero-sized types
+    if core::mem::size_of::<T>() != 0 {
+        let less = &mut |x: &T, y: &T| { metrics::compared(); cmp(x, y) == Ordering::Less };
+        sort_checked(v, &mut InsertionSort, less);
+    }
+}
+
+/// Sort `v` with a comparison function `cmp` as [`sort_verified_by`] does, sorting short pieces
+/// with `base` as [`crate::sort_with_base_by`] does. `base` sorts the original indices of the
+/// elements.
+///
+/// An unstable base sort is caught. This one sorts each piece descending and then reverses it,
+/// which reverses runs of equal elements:
+///
+/// ```should_panic
+/// use aerosort::{BaseSort, InsertionSort};
+///
+/// struct Reversing;
+///
+/// impl<T> BaseSort<T> for Reversing {
+///     fn sort<F: FnMut(&T, &T) -> bool>(&mut self, v: &mut [T], less: &mut F) {
+///         InsertionSort.sort(v, &mut |x, y| less(y, x));
+///         v.reverse();
+///     }
+/// }
+///
+/// let mut v: Vec<u32> = (0..1000).map(|i| i * 7919 % 1009 % 50).collect();
+/// aerosort::verify::sort_verified_with_base_by(&mut v, Reversing, |x, y| x.cmp(y));
+/// ```
+///
+/// # Panics
+///
+/// Panics if `base` leaves a slice unsorted, as well as on instability.
+#[inline(always)]
+pub fn sort_verified_with_base_by<T>(
+    v: &mut [T], base: impl BaseSort<u32>, mut cmp: impl FnMut(&T, &T) -> Ordering,
+) {
+    metrics::reset();
+
+    // Skip zero-sized types
+    if core::mem::size_of::<T>() != 0 {
+        let less = &mut |x: &T, y: &T| { metrics::compared(); cmp(x, y) == Ordering::Less };
+        sort_checked(v, &mut Checked(base), less);
+    }
+}
+
+// Sort `v` as `aero::sort_cancellable` does, sorting original indices rather than elements and
+// checking that equal elements kept their order.
+fn sort_checked<T, F: FnMut(&T, &T) -> bool>(
+    v: &mut [T], base: &mut impl BaseSort<u32>, less: &mut F,
+) {
+    let n = v.len();
+    let mut order = Vec::<u32>::new();
+    let config = &SortConfig::DEFAULT;
+
+    if u32::try_from(n).is_err() || order.try_reserve_exact(n).is_err() {
+        crate::aero::sort_full(v, &mut [], config, less);
+        return;
+    }
+
+    order.extend(0..n as u32);
+    crate::aero::sort_cancellable(&mut order, &mut [], config, base, &mut |&i, &j| {
+        less(&v[i as usize], &v[j as usize])
+    }, &mut || false);
+
+    check(v, &order, less);
+    crate::indirect::permute(v, &mut order);
+}